@@ -0,0 +1,251 @@
+// src/callbacks.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::web::{self, Bytes};
+use actix_web::HttpResponse;
+use chrono::Utc;
+use handlebars::Handlebars;
+use log::{error, info};
+use reqwest::Method;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::error::Error;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::models::{CallbackFilter, CallbackRecord, CallbackSpec, MockAPI};
+use crate::state::{callback_template_name, AppState};
+
+// Number of fired callbacks kept in the log
+const CALLBACK_LOG_SIZE: usize = 1000;
+
+// Give up on a callback target that does not answer
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl CallbackSpec {
+    /// Check the method and delay; the URL is checked once rendered
+    pub fn validate(&self) -> Result<(), String> {
+        if self.url.trim().is_empty() {
+            return Err("callback url is required".to_string());
+        }
+        Method::from_bytes(self.method.to_ascii_uppercase().as_bytes())
+            .map_err(|_| format!("'{}' is not a valid callback method", self.method))?;
+        self.delay.validate()
+    }
+}
+
+/// A rendered callback, ready to be sent
+pub struct PendingCallback {
+    pub mock_id: Uuid,
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+    pub delay: Duration,
+}
+
+/// Render every callback of a mock. `data` is the request context with the served
+/// response added as `response`. A callback whose templates fail to render comes back as
+/// a failed record for the callback log.
+pub fn render_callbacks(
+    handlebars: &Handlebars<'static>,
+    mock_id: Uuid,
+    mock: &MockAPI,
+    data: &Map<String, Value>,
+) -> Vec<Result<PendingCallback, Box<CallbackRecord>>> {
+    let render = |i: usize, part: &str| {
+        handlebars
+            .render(&callback_template_name(mock_id, i, part), data)
+            .map_err(|e| format!("Template rendering error in callback {} {}: {}", i, part, e))
+    };
+
+    mock.callbacks
+        .iter()
+        .enumerate()
+        .map(|(i, callback)| {
+            let method = callback.method.to_ascii_uppercase();
+            let rendered = (|| {
+                let mut headers = BTreeMap::new();
+                for name in callback.headers.keys() {
+                    headers.insert(name.clone(), render(i, &format!("header:{}", name))?);
+                }
+                Ok(PendingCallback {
+                    mock_id,
+                    method: method.clone(),
+                    url: render(i, "url")?,
+                    headers,
+                    body: render(i, "body")?,
+                    delay: callback.delay.sample(),
+                })
+            })();
+            rendered.map_err(|error: String| {
+                Box::new(CallbackRecord {
+                    id: Uuid::new_v4(),
+                    mock_id,
+                    timestamp: Utc::now(),
+                    method,
+                    url: callback.url.clone(),
+                    headers: BTreeMap::new(),
+                    body: String::new(),
+                    status: None,
+                    response_body: None,
+                    error: Some(error),
+                    duration_ms: 0,
+                })
+            })
+        })
+        .collect()
+}
+
+/// Response body firing the mock's callbacks once it is done: sent in full, or given up
+/// because the client went away. Event streams fire theirs when the stream ends.
+struct FireWhenSent {
+    body: BoxBody,
+    state: web::Data<AppState>,
+    callbacks: Vec<PendingCallback>,
+}
+
+impl MessageBody for FireWhenSent {
+    type Error = Box<dyn Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        Pin::new(&mut self.body).poll_next(cx)
+    }
+}
+
+impl Drop for FireWhenSent {
+    fn drop(&mut self) {
+        for callback in self.callbacks.drain(..) {
+            actix_web::rt::spawn(fire(self.state.clone(), callback));
+        }
+    }
+}
+
+/// Attach callbacks to a response, to be fired once the response has been sent
+pub fn fire_when_sent(
+    state: web::Data<AppState>,
+    callbacks: Vec<PendingCallback>,
+    response: HttpResponse,
+) -> HttpResponse {
+    if callbacks.is_empty() {
+        return response;
+    }
+    response.map_body(|_, body| {
+        BoxBody::new(FireWhenSent {
+            body,
+            state,
+            callbacks,
+        })
+    })
+}
+
+/// Send a callback after its delay and record the outcome in the callback log
+pub async fn fire(state: web::Data<AppState>, callback: PendingCallback) {
+    let delay = state.scale_delay(callback.delay);
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+
+    let mut record = CallbackRecord {
+        id: Uuid::new_v4(),
+        mock_id: callback.mock_id,
+        timestamp: Utc::now(),
+        method: callback.method.clone(),
+        url: callback.url.clone(),
+        headers: callback.headers.clone(),
+        body: callback.body.clone(),
+        status: None,
+        response_body: None,
+        error: None,
+        duration_ms: 0,
+    };
+
+    let started = Instant::now();
+    let method = Method::from_bytes(callback.method.as_bytes()).unwrap_or(Method::POST);
+    let mut request = state
+        .http_client
+        .request(method, &callback.url)
+        .timeout(CALLBACK_TIMEOUT)
+        .body(callback.body);
+    for (name, value) in &callback.headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    match request.send().await {
+        Ok(response) => {
+            record.status = Some(response.status().as_u16());
+            match response.text().await {
+                Ok(body) => record.response_body = Some(body),
+                Err(e) => record.error = Some(format!("Failed to read the response: {}", e)),
+            }
+        }
+        Err(e) => record.error = Some(e.to_string()),
+    }
+    record.duration_ms = started.elapsed().as_millis() as u64;
+
+    match (&record.status, &record.error) {
+        (Some(status), None) => info!(
+            "Callback {} {} answered {}",
+            record.method, record.url, status
+        ),
+        (_, error) => error!(
+            "Callback {} {} failed: {}",
+            record.method,
+            record.url,
+            error.as_deref().unwrap_or("")
+        ),
+    }
+    state.callback_log.record(record);
+}
+
+impl CallbackFilter {
+    fn accepts(&self, record: &CallbackRecord) -> bool {
+        let failed = record.error.is_some()
+            || record
+                .status
+                .is_none_or(|status| !(200..300).contains(&status));
+        self.mock_id.is_none_or(|id| record.mock_id == id)
+            && self.failed.is_none_or(|expected| failed == expected)
+    }
+}
+
+/// Bounded log of the callbacks fired, oldest first
+#[derive(Default)]
+pub struct CallbackLog {
+    records: Mutex<VecDeque<CallbackRecord>>,
+}
+
+impl CallbackLog {
+    pub fn record(&self, record: CallbackRecord) {
+        let mut records = self.records.lock().unwrap();
+        while records.len() >= CALLBACK_LOG_SIZE {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    pub fn records(&self, filter: &CallbackFilter) -> Vec<CallbackRecord> {
+        let records = self.records.lock().unwrap();
+        records
+            .iter()
+            .filter(|record| filter.accepts(record))
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.records.lock().unwrap().clear();
+    }
+}
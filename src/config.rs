@@ -0,0 +1,260 @@
+// src/config.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use clap::Parser;
+use std::path::{Path, PathBuf};
+
+use crate::models::{Settings, TlsConfig};
+
+// Shown instead of the sync token when the configuration is printed or served
+const REDACTED: &str = "********";
+
+/// Command-line flags. Each flag can also be set with its environment variable; both
+/// override the configuration file.
+#[derive(Debug, Parser)]
+#[command(
+    name = "OMock",
+    version,
+    about = "Mock server for HTTP, GraphQL, WebSocket and gRPC APIs"
+)]
+pub struct Cli {
+    /// Configuration file, TOML (.toml) or YAML (.yaml, .yml)
+    #[arg(short, long, env = "OMOCK_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address the HTTP listener binds to [default: 0.0.0.0]
+    #[arg(long, env = "OMOCK_HOST")]
+    pub host: Option<String>,
+
+    /// Port of the HTTP listener [default: 8080]
+    #[arg(short, long, env = "OMOCK_PORT")]
+    pub port: Option<u16>,
+
+    /// Worker threads; 0 starts one per CPU [default: 0]
+    #[arg(long, env = "OMOCK_WORKERS")]
+    pub workers: Option<usize>,
+
+    /// Maximum concurrent connections per worker [default: 20000]
+    #[arg(long, env = "OMOCK_MAX_CONNECTIONS")]
+    pub max_connections: Option<usize>,
+
+    /// Maximum pending connections [default: 1024]
+    #[arg(long, env = "OMOCK_BACKLOG")]
+    pub backlog: Option<u32>,
+
+    /// Serve gRPC mocks on their own listener [default: true]
+    #[arg(long, env = "OMOCK_GRPC_ENABLED", num_args = 0..=1, default_missing_value = "true")]
+    pub grpc: Option<bool>,
+
+    /// Port of the gRPC listener [default: 50051]
+    #[arg(long, env = "OMOCK_GRPC_PORT")]
+    pub grpc_port: Option<u16>,
+
+    /// Port of the HTTPS listener; HTTPS is off unless set
+    #[arg(long, env = "OMOCK_HTTPS_PORT")]
+    pub https_port: Option<u16>,
+
+    /// Serve Prometheus metrics [default: false]
+    #[arg(long, env = "METRICS_ENABLED", num_args = 0..=1, default_missing_value = "true")]
+    pub metrics: Option<bool>,
+
+    /// Port of the metrics listener [default: 9090]
+    #[arg(long, env = "METRICS_PORT")]
+    pub metrics_port: Option<u16>,
+
+    /// Token authenticating synchronization between pods
+    #[arg(long, env = "OMOCK_SYNC_TOKEN", hide_env_values = true)]
+    pub sync_token: Option<String>,
+
+    /// Port peers are reached on [default: the HTTP port]
+    #[arg(long, env = "OMOCK_PEER_PORT")]
+    pub peer_port: Option<u16>,
+
+    /// Seconds between synchronizations with peers [default: 60]
+    #[arg(long, env = "OMOCK_SYNC_INTERVAL_SECS")]
+    pub sync_interval_secs: Option<u64>,
+
+    /// Kubernetes namespace peers are looked up in [default: default]
+    #[arg(long, env = "K8S_NAMESPACE")]
+    pub namespace: Option<String>,
+
+    /// Value of the `app` label shared by the peer pods [default: omock]
+    #[arg(long, env = "APP_LABEL")]
+    pub app_label: Option<String>,
+
+    /// IP of this pod, left out of the peers
+    #[arg(long, env = "POD_IP")]
+    pub pod_ip: Option<String>,
+}
+
+impl Settings {
+    /// Build the effective configuration: defaults, then the configuration file, then the
+    /// environment and command-line flags
+    pub fn load(cli: &Cli) -> Result<Self, String> {
+        let mut settings = match &cli.config {
+            Some(path) => Settings::from_file(path)?,
+            None => Settings::default(),
+        };
+
+        settings.proxy.apply_env()?;
+        settings.journal.apply_env()?;
+        settings.near_miss.apply_env()?;
+        settings.cors.apply_env()?;
+        settings.apply_cli(cli);
+        if let Some(tls) = &mut settings.tls {
+            tls.apply_env()?;
+        }
+
+        settings.validate()?;
+
+        // Show and serve the worker count actually used
+        settings.server.workers = settings.workers();
+        Ok(settings)
+    }
+
+    /// Read a TOML or YAML configuration file, chosen by its extension
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("config file {}: {}", path.display(), e))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&content)
+                .map_err(|e| format!("config file {}: {}", path.display(), e)),
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&content)
+                .map_err(|e| format!("config file {}: {}", path.display(), e)),
+            _ => Err(format!(
+                "config file {}: expected a .toml, .yaml or .yml extension",
+                path.display()
+            )),
+        }
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        let server = &mut self.server;
+        if let Some(host) = &cli.host {
+            server.host = host.clone();
+        }
+        server.port = cli.port.unwrap_or(server.port);
+        server.workers = cli.workers.unwrap_or(server.workers);
+        server.max_connections = cli.max_connections.unwrap_or(server.max_connections);
+        server.backlog = cli.backlog.unwrap_or(server.backlog);
+        server.grpc_enabled = cli.grpc.unwrap_or(server.grpc_enabled);
+        server.grpc_port = cli.grpc_port.unwrap_or(server.grpc_port);
+
+        if let Some(port) = cli.https_port {
+            self.tls.get_or_insert_with(|| TlsConfig::new(port)).port = port;
+        }
+
+        self.metrics.enabled = cli.metrics.unwrap_or(self.metrics.enabled);
+        self.metrics.port = cli.metrics_port.unwrap_or(self.metrics.port);
+
+        if let Some(token) = &cli.sync_token {
+            self.sync.token = token.clone();
+        }
+        self.sync.peer_port = cli.peer_port.or(self.sync.peer_port);
+        self.sync.interval_secs = cli.sync_interval_secs.unwrap_or(self.sync.interval_secs);
+        if let Some(namespace) = &cli.namespace {
+            self.sync.namespace = namespace.clone();
+        }
+        if let Some(app_label) = &cli.app_label {
+            self.sync.app_label = app_label.clone();
+        }
+        self.sync.pod_ip = cli.pod_ip.clone().or(self.sync.pod_ip.take());
+    }
+
+    /// Check the listener, synchronization and feature settings
+    pub fn validate(&self) -> Result<(), String> {
+        if self.server.host.trim().is_empty() {
+            return Err("server.host must not be empty".to_string());
+        }
+        if self.server.max_connections == 0 {
+            return Err("server.max_connections must be greater than 0".to_string());
+        }
+        if self.sync.token.is_empty() {
+            return Err("sync.token must not be empty".to_string());
+        }
+        if self.sync.interval_secs == 0 {
+            return Err("sync.interval_secs must be greater than 0".to_string());
+        }
+        if self.sync.namespace.trim().is_empty() || self.sync.app_label.trim().is_empty() {
+            return Err("sync.namespace and sync.app_label must not be empty".to_string());
+        }
+        self.proxy.validate()?;
+        self.cors.validate()?;
+        if let Some(tls) = &self.tls {
+            tls.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Port the other pods are reached on for synchronization
+    pub fn peer_port(&self) -> u16 {
+        self.sync.peer_port.unwrap_or(self.server.port)
+    }
+
+    /// Worker threads of the HTTP listener
+    pub fn workers(&self) -> usize {
+        match self.server.workers {
+            0 => num_cpus::get(),
+            workers => workers,
+        }
+    }
+
+    /// The configuration with the sync token hidden, for printing and the admin endpoint
+    pub fn redacted(&self) -> Self {
+        let mut settings = self.clone();
+        settings.sync.token = REDACTED.to_string();
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("omock-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn environment_overrides_the_file_and_flags_override_both() {
+        let path = write_config(
+            "precedence.toml",
+            "[server]\nport = 1000\nbacklog = 100\nworkers = 2\n\n[journal]\nmax_entries = 10\n",
+        );
+        std::env::set_var("OMOCK_PORT", "2000");
+        std::env::set_var("OMOCK_BACKLOG", "200");
+        std::env::set_var("JOURNAL_MAX_ENTRIES", "20");
+        let cli = Cli::try_parse_from([
+            "OMock",
+            "--config",
+            path.to_str().unwrap(),
+            "--port",
+            "3000",
+        ]);
+        std::env::remove_var("OMOCK_PORT");
+        std::env::remove_var("OMOCK_BACKLOG");
+        let settings = Settings::load(&cli.unwrap());
+        std::env::remove_var("JOURNAL_MAX_ENTRIES");
+        std::fs::remove_file(&path).unwrap();
+
+        let settings = settings.unwrap();
+        assert_eq!(settings.server.port, 3000);
+        assert_eq!(settings.server.backlog, 200);
+        assert_eq!(settings.server.workers, 2);
+        assert_eq!(settings.journal.max_entries, 20);
+        assert_eq!(settings.sync.namespace, "default");
+    }
+
+    #[test]
+    fn unknown_keys_in_nested_sections_are_rejected() {
+        let path = write_config("typo.toml", "[cors]\nallowed_origin = [\"*\"]\n");
+        let result = Settings::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.unwrap_err().contains("unknown field"));
+    }
+}
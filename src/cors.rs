@@ -0,0 +1,346 @@
+// src/cors.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+
+use crate::models::CorsConfig;
+use crate::state::AppState;
+
+impl CorsConfig {
+    /// Override the global policy with `CORS_ALLOWED_ORIGINS`, `CORS_ALLOWED_METHODS`,
+    /// `CORS_ALLOWED_HEADERS`, `CORS_EXPOSED_HEADERS` (comma-separated),
+    /// `CORS_ALLOW_CREDENTIALS` and `CORS_MAX_AGE`. Setting origins enables CORS.
+    pub fn apply_env(&mut self) -> Result<(), String> {
+        let list = |name: &str| {
+            std::env::var(name).ok().map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        if let Some(origins) = list("CORS_ALLOWED_ORIGINS") {
+            self.enabled = true;
+            self.allowed_origins = origins;
+        }
+        if let Some(methods) = list("CORS_ALLOWED_METHODS") {
+            self.allowed_methods = methods;
+        }
+        if let Some(headers) = list("CORS_ALLOWED_HEADERS") {
+            self.allowed_headers = headers;
+        }
+        if let Some(headers) = list("CORS_EXPOSED_HEADERS") {
+            self.exposed_headers = headers;
+        }
+        if let Ok(value) = std::env::var("CORS_ALLOW_CREDENTIALS") {
+            self.allow_credentials = value.parse().map_err(|_| {
+                format!(
+                    "CORS_ALLOW_CREDENTIALS must be true or false, got '{}'",
+                    value
+                )
+            })?;
+        }
+        if let Ok(value) = std::env::var("CORS_MAX_AGE") {
+            self.max_age = Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("CORS_MAX_AGE must be a number, got '{}'", value))?,
+            );
+        }
+        Ok(())
+    }
+
+    /// Check the origins, methods and header names
+    pub fn validate(&self) -> Result<(), String> {
+        if self.enabled && self.allowed_origins.is_empty() {
+            return Err("cors.allowed_origins must not be empty".to_string());
+        }
+        for origin in &self.allowed_origins {
+            if HeaderValue::from_str(origin).is_err() {
+                return Err(format!("cors origin '{}' is invalid", origin));
+            }
+        }
+        for method in &self.allowed_methods {
+            Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                .map_err(|_| format!("'{}' is not a valid cors method", method))?;
+        }
+        for name in self.allowed_headers.iter().chain(&self.exposed_headers) {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("cors header name '{}' is invalid", name))?;
+        }
+        Ok(())
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// `Access-Control-Allow-Origin` for an allowed origin. Credentialed requests need
+    /// the origin itself rather than `*`.
+    fn allow_origin(&self, origin: &str) -> String {
+        if !self.allow_credentials && self.allowed_origins.iter().any(|o| o == "*") {
+            "*".to_string()
+        } else {
+            origin.to_string()
+        }
+    }
+
+    /// Answer a preflight for `method` and the requested headers, or say why it is refused
+    fn preflight(
+        &self,
+        origin: &str,
+        method: &str,
+        requested_headers: &str,
+    ) -> Result<HttpResponse, String> {
+        if !self.allows_origin(origin) {
+            return Err(format!("CORS origin {} is not allowed", origin));
+        }
+        if !self.allowed_methods.is_empty()
+            && !self
+                .allowed_methods
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(method))
+        {
+            return Err(format!("CORS method {} is not allowed", method));
+        }
+        let requested: Vec<&str> = requested_headers
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect();
+        if !self.allowed_headers.is_empty() {
+            if let Some(name) = requested.iter().find(|name| {
+                !self
+                    .allowed_headers
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(name))
+            }) {
+                return Err(format!("CORS header {} is not allowed", name));
+            }
+        }
+
+        let mut response = HttpResponse::NoContent();
+        response.insert_header((
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            self.allow_origin(origin),
+        ));
+        response.insert_header((header::VARY, "Origin"));
+        let methods = if self.allowed_methods.is_empty() {
+            method.to_string()
+        } else {
+            self.allowed_methods.join(", ").to_ascii_uppercase()
+        };
+        response.insert_header((header::ACCESS_CONTROL_ALLOW_METHODS, methods));
+        let headers = if self.allowed_headers.is_empty() {
+            requested.join(", ")
+        } else {
+            self.allowed_headers.join(", ")
+        };
+        if !headers.is_empty() {
+            response.insert_header((header::ACCESS_CONTROL_ALLOW_HEADERS, headers));
+        }
+        if self.allow_credentials {
+            response.insert_header((header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true"));
+        }
+        if let Some(max_age) = self.max_age {
+            response.insert_header((header::ACCESS_CONTROL_MAX_AGE, max_age.to_string()));
+        }
+        Ok(response.finish())
+    }
+
+    /// Add the CORS headers of an actual cross-origin request, unless the mock set its own
+    fn decorate(&self, origin: &str, headers: &mut HeaderMap) {
+        if !self.allows_origin(origin) || headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        {
+            return;
+        }
+        if let Ok(value) = HeaderValue::from_str(&self.allow_origin(origin)) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+        }
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        if !self.exposed_headers.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&self.exposed_headers.join(", ")) {
+                headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, value);
+            }
+        }
+    }
+}
+
+/// The policy for a request: the first mock serving it that overrides CORS, else the
+/// global policy
+fn policy_for(state: &AppState, method: &str, path: &str) -> CorsConfig {
+    path.strip_prefix("/mock/")
+        .and_then(|api_name| {
+            state
+                .route_candidates(method, api_name)
+                .into_iter()
+                .find_map(|(mock_id, _)| state.mocks.get(&mock_id)?.cors.clone())
+        })
+        .unwrap_or_else(|| state.cors.read().unwrap().clone())
+}
+
+/// Middleware answering CORS preflights and adding `Access-Control-*` headers to
+/// cross-origin responses, on mock and admin routes alike
+pub async fn cors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let state = req.app_data::<web::Data<AppState>>().cloned();
+    let origin = req
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let (state, origin) = match (state, origin) {
+        (Some(state), Some(origin)) => (state, origin),
+        _ => return Ok(next.call(req).await?.map_into_left_body()),
+    };
+
+    // A preflight asks about the method of the request to come
+    let preflight_method = req
+        .headers()
+        .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| req.method() == Method::OPTIONS)
+        .map(str::to_ascii_uppercase);
+    let method = preflight_method
+        .clone()
+        .unwrap_or_else(|| req.method().to_string());
+    let policy = policy_for(&state, &method, req.path());
+    if !policy.enabled {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+
+    if let Some(method) = preflight_method {
+        let requested_headers = req
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let response = policy
+            .preflight(&origin, &method, requested_headers)
+            .unwrap_or_else(|e| HttpResponse::Forbidden().json(e));
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    let mut response = next.call(req).await?;
+    policy.decorate(&origin, response.headers_mut());
+    Ok(response.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(origins: &[&str], methods: &[&str], headers: &[&str]) -> CorsConfig {
+        let list = |items: &[&str]| items.iter().map(|s| s.to_string()).collect();
+        CorsConfig {
+            enabled: true,
+            allowed_origins: list(origins),
+            allowed_methods: list(methods),
+            allowed_headers: list(headers),
+            ..Default::default()
+        }
+    }
+
+    fn header(response: &HttpResponse, name: HeaderName) -> Option<&str> {
+        response.headers().get(name).and_then(|v| v.to_str().ok())
+    }
+
+    #[test]
+    fn preflight_echoes_the_request_when_nothing_is_restricted() {
+        let cors = config(&["*"], &[], &[]);
+        let response = cors
+            .preflight("https://app.test", "PUT", "x-token, content-type")
+            .unwrap();
+        assert_eq!(response.status(), 204);
+        assert_eq!(
+            header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some("*")
+        );
+        assert_eq!(
+            header(&response, header::ACCESS_CONTROL_ALLOW_METHODS),
+            Some("PUT")
+        );
+        assert_eq!(
+            header(&response, header::ACCESS_CONTROL_ALLOW_HEADERS),
+            Some("x-token, content-type")
+        );
+        assert_eq!(header(&response, header::ACCESS_CONTROL_MAX_AGE), None);
+    }
+
+    #[test]
+    fn preflight_refuses_what_is_not_allowed() {
+        let cors = config(&["https://app.test"], &["get", "post"], &["X-Token"]);
+        assert_eq!(
+            cors.preflight("https://evil.test", "GET", "").unwrap_err(),
+            "CORS origin https://evil.test is not allowed"
+        );
+        assert_eq!(
+            cors.preflight("https://app.test", "DELETE", "")
+                .unwrap_err(),
+            "CORS method DELETE is not allowed"
+        );
+        assert_eq!(
+            cors.preflight("https://app.test", "POST", "x-token, x-other")
+                .unwrap_err(),
+            "CORS header x-other is not allowed"
+        );
+
+        let response = cors
+            .preflight("https://APP.test", "post", "x-token")
+            .unwrap();
+        assert_eq!(
+            header(&response, header::ACCESS_CONTROL_ALLOW_METHODS),
+            Some("GET, POST")
+        );
+        assert_eq!(
+            header(&response, header::ACCESS_CONTROL_ALLOW_HEADERS),
+            Some("X-Token")
+        );
+    }
+
+    #[test]
+    fn credentialed_preflight_names_the_origin() {
+        let cors = CorsConfig {
+            allow_credentials: true,
+            max_age: Some(600),
+            ..config(&["*"], &[], &[])
+        };
+        let response = cors.preflight("https://app.test", "GET", "").unwrap();
+        assert_eq!(
+            header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some("https://app.test")
+        );
+        assert_eq!(
+            header(&response, header::ACCESS_CONTROL_ALLOW_CREDENTIALS),
+            Some("true")
+        );
+        assert_eq!(
+            header(&response, header::ACCESS_CONTROL_MAX_AGE),
+            Some("600")
+        );
+        assert_eq!(
+            header(&response, header::ACCESS_CONTROL_ALLOW_HEADERS),
+            None
+        );
+    }
+}
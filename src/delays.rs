@@ -0,0 +1,218 @@
+// src/delays.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use rand::Rng;
+use std::time::Duration;
+
+use crate::models::{DelayDistribution, DelaySpec};
+use crate::state::AppState;
+
+// z-score of the 99th percentile of the standard normal distribution
+const Z_P99: f64 = 2.326_347_874;
+
+// Longest delay ever applied; longer samples and scaled delays are clamped to it
+pub const MAX_DELAY: Duration = Duration::from_secs(3600);
+
+// Largest accepted global delay multiplier
+pub const MAX_DELAY_MULTIPLIER: f64 = 1000.0;
+
+impl Default for DelaySpec {
+    fn default() -> Self {
+        DelaySpec::Fixed(0)
+    }
+}
+
+impl DelaySpec {
+    /// Check that the distribution parameters are consistent
+    pub fn validate(&self) -> Result<(), String> {
+        let distribution = match self {
+            DelaySpec::Fixed(_) => return Ok(()),
+            DelaySpec::Distribution(distribution) => distribution,
+        };
+        match *distribution {
+            DelayDistribution::Uniform { min, max } if min > max => Err(format!(
+                "uniform delay min ({}) must not exceed max ({})",
+                min, max
+            )),
+            DelayDistribution::Normal { median, p99 }
+            | DelayDistribution::Lognormal { median, p99 }
+                if p99 < median =>
+            {
+                Err(format!(
+                    "delay p99 ({}) must not be below the median ({})",
+                    p99, median
+                ))
+            }
+            DelayDistribution::Lognormal { median: 0, .. } => {
+                Err("lognormal delay median must be greater than 0".to_string())
+            }
+            DelayDistribution::Spike { probability, .. } if !(0.0..=1.0).contains(&probability) => {
+                Err(format!(
+                    "spike probability must be between 0 and 1, got {}",
+                    probability
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Draw the delay for one request
+    pub fn sample(&self) -> Duration {
+        let distribution = match self {
+            DelaySpec::Fixed(ms) => return Duration::from_millis(*ms),
+            DelaySpec::Distribution(distribution) => distribution,
+        };
+        let mut rng = rand::thread_rng();
+        let ms = match *distribution {
+            DelayDistribution::Fixed { ms } => ms as f64,
+            DelayDistribution::Uniform { min, max } => rng.gen_range(min..=max) as f64,
+            DelayDistribution::Normal { median, p99 } => {
+                let sigma = (p99 - median) as f64 / Z_P99;
+                median as f64 + sigma * standard_normal(&mut rng)
+            }
+            DelayDistribution::Lognormal { median, p99 } => {
+                let mu = (median as f64).ln();
+                let sigma = (p99 as f64 / median as f64).ln() / Z_P99;
+                (mu + sigma * standard_normal(&mut rng)).exp()
+            }
+            DelayDistribution::Spike {
+                base,
+                spike,
+                probability,
+            } => {
+                if rng.gen::<f64>() < probability {
+                    spike as f64
+                } else {
+                    base as f64
+                }
+            }
+        };
+        clamp_secs(ms / 1000.0)
+    }
+}
+
+impl AppState {
+    /// Scale a delay by the global multiplier, clamped to `MAX_DELAY`
+    pub fn scale_delay(&self, delay: Duration) -> Duration {
+        if delay.is_zero() {
+            return delay;
+        }
+        clamp_secs(delay.as_secs_f64() * *self.delay_multiplier.read().unwrap())
+    }
+}
+
+/// A delay of `secs` seconds, clamped to `[0, MAX_DELAY]`
+fn clamp_secs(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs.max(0.0))
+        .unwrap_or(MAX_DELAY)
+        .min(MAX_DELAY)
+}
+
+/// Sample from N(0, 1) using the Box-Muller transform
+fn standard_normal(rng: &mut impl Rng) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>(); // (0, 1], keeps ln() finite
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Settings;
+    use reqwest::Client;
+
+    fn spec(distribution: DelayDistribution) -> DelaySpec {
+        DelaySpec::Distribution(distribution)
+    }
+
+    #[test]
+    fn validate_rejects_inconsistent_distributions() {
+        assert!(DelaySpec::Fixed(u64::MAX).validate().is_ok());
+        assert!(spec(DelayDistribution::Uniform { min: 5, max: 5 })
+            .validate()
+            .is_ok());
+        assert!(spec(DelayDistribution::Uniform { min: 6, max: 5 })
+            .validate()
+            .is_err());
+        assert!(spec(DelayDistribution::Normal {
+            median: 100,
+            p99: 99
+        })
+        .validate()
+        .is_err());
+        assert!(spec(DelayDistribution::Lognormal { median: 0, p99: 10 })
+            .validate()
+            .is_err());
+        assert!(spec(DelayDistribution::Lognormal {
+            median: 10,
+            p99: 10
+        })
+        .validate()
+        .is_ok());
+        for probability in [-0.1, 1.1, f64::NAN] {
+            let spike = DelayDistribution::Spike {
+                base: 0,
+                spike: 10,
+                probability,
+            };
+            assert!(spec(spike).validate().is_err(), "{}", probability);
+        }
+    }
+
+    #[test]
+    fn samples_stay_within_bounds() {
+        let uniform = spec(DelayDistribution::Uniform { min: 10, max: 20 });
+        let spike = spec(DelayDistribution::Spike {
+            base: 5,
+            spike: 500,
+            probability: 1.0,
+        });
+        let normal = spec(DelayDistribution::Normal {
+            median: 0,
+            p99: 1000,
+        });
+        for _ in 0..200 {
+            let ms = uniform.sample().as_millis();
+            assert!((10..=20).contains(&ms), "{}", ms);
+            assert_eq!(spike.sample(), Duration::from_millis(500));
+            // Negative normal samples are clamped to zero
+            assert!(normal.sample() <= MAX_DELAY);
+        }
+        assert_eq!(DelaySpec::Fixed(250).sample(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn huge_delays_are_clamped() {
+        let huge = spec(DelayDistribution::Fixed { ms: u64::MAX });
+        assert_eq!(huge.sample(), MAX_DELAY);
+        let lognormal = spec(DelayDistribution::Lognormal {
+            median: 1,
+            p99: u64::MAX,
+        });
+        for _ in 0..50 {
+            assert!(lognormal.sample() <= MAX_DELAY);
+        }
+        assert_eq!(clamp_secs(-1.0), Duration::ZERO);
+        assert_eq!(clamp_secs(f64::INFINITY), MAX_DELAY);
+        assert_eq!(clamp_secs(f64::NAN), Duration::ZERO);
+    }
+
+    #[test]
+    fn scale_delay_applies_the_multiplier() {
+        let state = AppState::new(Settings::default(), Client::new(), None);
+        *state.delay_multiplier.write().unwrap() = 2.5;
+        assert_eq!(
+            state.scale_delay(Duration::from_millis(100)),
+            Duration::from_millis(250)
+        );
+        assert_eq!(state.scale_delay(Duration::ZERO), Duration::ZERO);
+
+        *state.delay_multiplier.write().unwrap() = MAX_DELAY_MULTIPLIER;
+        assert_eq!(state.scale_delay(Duration::from_secs(60)), MAX_DELAY);
+
+        *state.delay_multiplier.write().unwrap() = 0.0;
+        assert_eq!(state.scale_delay(Duration::from_secs(1)), Duration::ZERO);
+    }
+}
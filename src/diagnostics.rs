@@ -0,0 +1,306 @@
+// src/diagnostics.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::graphql::GraphQLRequest;
+use crate::matchers::RequestView;
+use crate::models::{JournalFilter, MockAPI, NearMiss, NearMissConfig, UnmatchedRequest};
+use crate::router::{PathPattern, ANY_METHOD};
+use crate::state::AppState;
+
+impl NearMissConfig {
+    /// Override the diagnostics switch with `NEAR_MISS_DIAGNOSTICS` (true/false)
+    pub fn apply_env(&mut self) -> Result<(), String> {
+        if let Ok(value) = std::env::var("NEAR_MISS_DIAGNOSTICS") {
+            self.enabled = value.parse().map_err(|_| {
+                format!(
+                    "NEAR_MISS_DIAGNOSTICS must be true or false, got '{}'",
+                    value
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The mocks closest to serving a request, closest path first, then fewest reasons
+pub fn near_misses(
+    state: &AppState,
+    method: &str,
+    api_name: &str,
+    view: &RequestView,
+    limit: usize,
+) -> Vec<NearMiss> {
+    let method = method.to_ascii_uppercase();
+    let pattern_matches = pattern_matches(state, api_name);
+    // gRPC mocks are only served on the gRPC listener, never to these requests
+    let mut misses: Vec<NearMiss> = state
+        .mocks
+        .iter()
+        .filter(|entry| entry.value().grpc.is_none())
+        .map(|entry| {
+            let mock = entry.value();
+            let path_matches = if PathPattern::is_pattern(&mock.api_name) {
+                pattern_matches.contains(entry.key())
+            } else {
+                mock.api_name == api_name
+            };
+            diagnose(state, &method, api_name, path_matches, view, mock)
+        })
+        .collect();
+    misses.sort_by(|a, b| {
+        a.path_distance
+            .cmp(&b.path_distance)
+            .then(a.reasons.len().cmp(&b.reasons.len()))
+    });
+    misses.truncate(limit);
+    misses
+}
+
+/// Journaled requests no mock served, each with its closest mocks as they are now
+pub fn unmatched_requests(
+    state: &AppState,
+    filter: &JournalFilter,
+    limit: usize,
+) -> Vec<UnmatchedRequest> {
+    let filter = JournalFilter {
+        matched: Some(false),
+        ..filter.clone()
+    };
+    state
+        .journal
+        .entries(&filter)
+        .into_iter()
+        .map(|request| {
+            let view = RequestView::from_entry(&request);
+            let near_misses = near_misses(state, &request.method, &request.path, &view, limit);
+            UnmatchedRequest {
+                request,
+                near_misses,
+            }
+        })
+        .collect()
+}
+
+/// Ids of the pattern mocks whose api_name matches the path for any method, using the
+/// patterns already compiled in the route index
+fn pattern_matches(state: &AppState, api_name: &str) -> HashSet<Uuid> {
+    state
+        .path_patterns
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|pattern| pattern.captures(api_name).is_some())
+        .map(|pattern| pattern.entry.mock_id)
+        .collect()
+}
+
+/// Explain why a mock does not serve the request
+fn diagnose(
+    state: &AppState,
+    method: &str,
+    api_name: &str,
+    path_matches: bool,
+    view: &RequestView,
+    mock: &MockAPI,
+) -> NearMiss {
+    let mock_id = mock.id.unwrap_or_default();
+    let methods = mock.method.methods();
+    let mut reasons = Vec::new();
+
+    let path_distance = if path_matches {
+        0
+    } else {
+        reasons.push(format!(
+            "path: '{}' does not match '{}'",
+            api_name, mock.api_name
+        ));
+        levenshtein(api_name, &mock.api_name).max(1)
+    };
+
+    let method_matches = methods
+        .iter()
+        .any(|m| m == ANY_METHOD || m == method || (method == "HEAD" && m == "GET"));
+    if !method_matches {
+        reasons.push(format!(
+            "method: {} is not one of {}",
+            method,
+            methods.join(", ")
+        ));
+    }
+
+    // WebSocket upgrades are only accepted by mocks with a `websocket` script
+    let upgrade = view
+        .headers
+        .get("upgrade")
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    if upgrade && mock.websocket.is_none() {
+        reasons.push("websocket: mock does not accept WebSocket upgrades".to_string());
+    }
+
+    if let Some(matchers) = &mock.matchers {
+        reasons.extend(matchers.mismatches(view));
+    }
+
+    if let Some(expected) = mock.graphql.as_ref().and_then(|g| g.operation.as_ref()) {
+        let body = view
+            .body
+            .as_ref()
+            .map(|b| b.to_string())
+            .unwrap_or_default();
+        let operation = GraphQLRequest::parse(body.as_bytes(), &view.query)
+            .and_then(|request| request.operation_name);
+        if operation.as_ref() != Some(expected) {
+            reasons.push(format!(
+                "graphql operation: expected {}, got {}",
+                expected,
+                operation.as_deref().unwrap_or("none")
+            ));
+        }
+    }
+
+    if let (Some(scenario), Some(required_state)) = (&mock.scenario, &mock.required_state) {
+        let current = state.scenario_state(scenario);
+        if current != *required_state {
+            reasons.push(format!(
+                "scenario {}: in state {}, requires {}",
+                scenario, current, required_state
+            ));
+        }
+    }
+
+    if !mock.responses.is_empty() {
+        let calls = state
+            .sequence_counters
+            .get(&mock_id)
+            .map(|calls| *calls)
+            .unwrap_or(0);
+        if mock
+            .sequence_mode
+            .step(calls, mock.responses.len())
+            .is_none()
+        {
+            reasons.push(format!("sequence: exhausted after {} calls", calls));
+        }
+    }
+
+    NearMiss {
+        mock_id,
+        api_name: mock.api_name.clone(),
+        methods,
+        path_distance,
+        reasons,
+    }
+}
+
+/// Edit distance between two strings, counted in characters
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{MethodSpec, Settings, WebSocketSpec};
+    use reqwest::Client;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn state_with(mocks: Vec<MockAPI>) -> AppState {
+        let state = AppState::new(Settings::default(), Client::new(), None);
+        for mut mock in mocks {
+            let mock_id = Uuid::new_v4();
+            mock.id = Some(mock_id);
+            state.index_mock(mock_id, &mock).unwrap();
+            state.mocks.insert(mock_id, mock);
+        }
+        state
+    }
+
+    fn mock(api_name: &str, method: &str) -> MockAPI {
+        MockAPI {
+            api_name: api_name.to_string(),
+            method: MethodSpec::One(method.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn view(headers: &[(&str, &str)]) -> RequestView {
+        RequestView {
+            protocol: "HTTP/1.1".to_string(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            query: HashMap::new(),
+            body: None,
+        }
+    }
+
+    #[test]
+    fn levenshtein_counts_character_edits() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("users", "users"), 0);
+        assert_eq!(levenshtein("users", ""), 5);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("users/1", "user/1"), 1);
+        assert_eq!(levenshtein("héllo", "hello"), 1);
+    }
+
+    #[test]
+    fn closest_path_comes_first_then_fewest_reasons() {
+        let state = state_with(vec![
+            mock("orders", "GET"),
+            mock("users/{id}", "POST"),
+            mock("users/{id}", "DELETE"),
+            mock("user/1", "GET"),
+        ]);
+        let misses = near_misses(&state, "get", "users/1", &view(&[]), 3);
+        let ranked: Vec<(&str, usize, usize)> = misses
+            .iter()
+            .map(|m| (m.api_name.as_str(), m.path_distance, m.reasons.len()))
+            .collect();
+        assert_eq!(ranked[0].1, 0);
+        assert_eq!(ranked[0].0, "users/{id}");
+        assert_eq!(ranked[1].0, "users/{id}");
+        assert_eq!(ranked[2], ("user/1", 1, 1));
+        assert_eq!(misses[0].reasons.len(), 1);
+        assert!(misses[0].reasons[0].starts_with("method: GET is not one of"));
+    }
+
+    #[test]
+    fn grpc_mocks_are_skipped_and_upgrades_need_a_websocket_mock() {
+        let grpc = MockAPI {
+            grpc: Some(Default::default()),
+            ..mock("chat", "POST")
+        };
+        let socket = MockAPI {
+            websocket: Some(WebSocketSpec::default()),
+            ..mock("chat", "ANY")
+        };
+        let state = state_with(vec![grpc, mock("chat", "GET"), socket]);
+
+        let upgrade = view(&[("upgrade", "websocket")]);
+        let misses = near_misses(&state, "GET", "chat", &upgrade, 10);
+        assert_eq!(misses.len(), 2);
+        assert!(misses[0].reasons.is_empty());
+        assert_eq!(
+            misses[1].reasons,
+            ["websocket: mock does not accept WebSocket upgrades"]
+        );
+    }
+}
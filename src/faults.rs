@@ -0,0 +1,138 @@
+// src/faults.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use actix_web::body::SizedStream;
+use actix_web::dev::Extensions;
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use futures::stream::{self, StreamExt};
+use log::{error, info};
+use rand::Rng;
+use std::any::Any;
+use std::io;
+use tokio::time::{sleep, Duration};
+
+use crate::models::{FaultKind, FaultSpec};
+
+// Time given to actix to flush what was sent before a connection is dropped
+const FLUSH_PAUSE: Duration = Duration::from_millis(50);
+
+/// Raw socket of the connection a request arrived on, stored by `HttpServer::on_connect`
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionFd(pub std::os::unix::io::RawFd);
+
+/// `HttpServer::on_connect` callback storing the socket of each new TCP or TLS connection
+pub fn remember_connection(connection: &dyn Any, data: &mut Extensions) {
+    #[cfg(unix)]
+    if let Some(stream) = connection.downcast_ref::<actix_web::rt::net::TcpStream>() {
+        use std::os::unix::io::AsRawFd;
+        data.insert(ConnectionFd(stream.as_raw_fd()));
+    }
+    #[cfg(unix)]
+    if let Some(stream) = connection
+        .downcast_ref::<actix_tls::accept::rustls_0_23::TlsStream<actix_web::rt::net::TcpStream>>()
+    {
+        use std::os::unix::io::AsRawFd;
+        data.insert(ConnectionFd(stream.get_ref().0.as_raw_fd()));
+    }
+}
+
+impl FaultSpec {
+    /// Roll the dice: returns true when the fault should be applied to this request
+    pub fn triggers(&self) -> bool {
+        self.probability >= 1.0 || rand::thread_rng().gen::<f64>() < self.probability
+    }
+
+    /// Check that the probability is within [0, 1]
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.probability) {
+            return Err(format!(
+                "fault probability must be between 0 and 1, got {}",
+                self.probability
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Build the faulty response for a rendered body
+pub fn fault_response(
+    kind: FaultKind,
+    req: &HttpRequest,
+    mut response: HttpResponseBuilder,
+    body: String,
+) -> HttpResponse {
+    info!("Injecting {:?} fault for {}", kind, req.path());
+    let body = Bytes::from(body);
+    let half = body.slice(..body.len() / 2);
+
+    match kind {
+        FaultKind::ConnectionReset => {
+            // An abortive close makes the kernel send RST instead of FIN
+            set_abortive_close(req);
+            response.body(SizedStream::new(body.len() as u64, failing_stream()))
+        }
+        FaultKind::EmptyReply => {
+            shutdown_connection(req);
+            response.body(SizedStream::new(body.len() as u64, failing_stream()))
+        }
+        FaultKind::TruncatedBody => {
+            // Announce the full length, send half of it and drop the connection. The short
+            // pause lets the headers and the first half be flushed before the connection drops.
+            let chunks = stream::once(async move { Ok(half) })
+                .chain(stream::once(sleep(FLUSH_PAUSE)).filter_map(|_| async { None }))
+                .chain(failing_stream());
+            response.body(SizedStream::new(body.len() as u64, chunks))
+        }
+        FaultKind::MalformedBody => {
+            // Keep the first half and replace the rest with random bytes
+            let mut rng = rand::thread_rng();
+            let mut garbled = half.to_vec();
+            garbled.extend((half.len()..body.len().max(8)).map(|_| rng.gen::<u8>()));
+            response.body(garbled)
+        }
+        FaultKind::NeverComplete => {
+            // Send the headers and half of the body, then stall forever
+            let chunks =
+                stream::once(async move { Ok::<_, io::Error>(half) }).chain(stream::pending());
+            response.body(SizedStream::new(body.len() as u64, chunks))
+        }
+    }
+}
+
+/// Body stream that fails immediately, making actix drop the connection
+fn failing_stream() -> stream::Once<futures::future::Ready<Result<Bytes, io::Error>>> {
+    stream::once(futures::future::ready(Err(io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "injected fault",
+    ))))
+}
+
+#[cfg(unix)]
+fn with_socket(req: &HttpRequest, f: impl FnOnce(socket2::SockRef<'_>) -> io::Result<()>) {
+    use std::os::unix::io::BorrowedFd;
+
+    let fd = match req.conn_data::<ConnectionFd>() {
+        Some(fd) => fd.0,
+        None => return,
+    };
+    // SAFETY: the connection, and therefore its socket, outlives the request being handled
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+    if let Err(e) = f(socket2::SockRef::from(&fd)) {
+        error!("Error injecting connection fault: {}", e);
+    }
+}
+
+#[cfg(not(unix))]
+fn with_socket(_req: &HttpRequest, _f: impl FnOnce(socket2::SockRef<'_>) -> io::Result<()>) {}
+
+fn set_abortive_close(req: &HttpRequest) {
+    with_socket(req, |socket| socket.set_linger(Some(Duration::ZERO)));
+}
+
+fn shutdown_connection(req: &HttpRequest) {
+    with_socket(req, |socket| socket.shutdown(std::net::Shutdown::Both));
+}
@@ -0,0 +1,171 @@
+// src/graphql.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// First named operation in a GraphQL document
+static OPERATION_RE: OnceLock<Regex> = OnceLock::new();
+
+/// The parts of a GraphQL request used for matching and templating
+pub struct GraphQLRequest {
+    pub operation_name: Option<String>,
+    pub query: String,
+    pub variables: Value,
+}
+
+/// Whether a query parameter is part of a GraphQL GET request
+pub fn is_request_param(name: &str) -> bool {
+    matches!(name, "query" | "operationName" | "variables")
+}
+
+impl GraphQLRequest {
+    /// Parse a GraphQL request from a JSON POST body, or from the query string of a GET
+    pub fn parse(body: &[u8], query: &HashMap<String, String>) -> Option<Self> {
+        let (document, operation_name, variables) = match serde_json::from_slice::<Value>(body).ok()
+        {
+            Some(Value::Object(fields)) => (
+                fields.get("query")?.as_str()?.to_string(),
+                fields
+                    .get("operationName")
+                    .and_then(Value::as_str)
+                    .map(str::to_string),
+                fields.get("variables").cloned().unwrap_or(Value::Null),
+            ),
+            _ => (
+                query.get("query")?.clone(),
+                query.get("operationName").cloned(),
+                query
+                    .get("variables")
+                    .and_then(|v| serde_json::from_str(v).ok())
+                    .unwrap_or(Value::Null),
+            ),
+        };
+
+        // Without an explicit operationName, use the name of the first operation
+        let operation_name = operation_name.or_else(|| {
+            OPERATION_RE
+                .get_or_init(|| {
+                    Regex::new(r"\b(?:query|mutation|subscription)\s+([_A-Za-z][_0-9A-Za-z]*)")
+                        .unwrap()
+                })
+                .captures(&document)
+                .map(|caps| caps[1].to_string())
+        });
+
+        Some(GraphQLRequest {
+            operation_name,
+            query: document,
+            variables: match variables {
+                Value::Null => Value::Object(Map::new()),
+                other => other,
+            },
+        })
+    }
+}
+
+/// Wrap rendered data and errors into a `{"data": ..., "errors": [...]}` response
+pub fn envelope(data: &str, errors: Option<&str>) -> Result<String, String> {
+    let data: Value = if data.trim().is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(data).map_err(|e| format!("GraphQL data is not valid JSON: {}", e))?
+    };
+
+    let mut response = Map::new();
+    response.insert("data".to_string(), data);
+    if let Some(errors) = errors {
+        let errors: Value = serde_json::from_str(errors)
+            .map_err(|e| format!("GraphQL errors are not valid JSON: {}", e))?;
+        response.insert("errors".to_string(), errors);
+    }
+    Ok(Value::Object(response).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_post_body() {
+        let body = json!({
+            "query": "query GetUser($id: ID!) { user(id: $id) { name } }",
+            "variables": { "id": "42" }
+        });
+        let request = GraphQLRequest::parse(body.to_string().as_bytes(), &HashMap::new()).unwrap();
+        assert_eq!(request.operation_name.as_deref(), Some("GetUser"));
+        assert_eq!(request.variables, json!({ "id": "42" }));
+        assert!(request.query.starts_with("query GetUser"));
+    }
+
+    #[test]
+    fn parse_get_query_string() {
+        let query = params(&[
+            ("query", "{ me { name } }"),
+            ("operationName", "Me"),
+            ("variables", r#"{"verbose": true}"#),
+        ]);
+        let request = GraphQLRequest::parse(b"", &query).unwrap();
+        assert_eq!(request.operation_name.as_deref(), Some("Me"));
+        assert_eq!(request.query, "{ me { name } }");
+        assert_eq!(request.variables, json!({ "verbose": true }));
+
+        // Unparsable variables are treated as none
+        let query = params(&[("query", "{ me { name } }"), ("variables", "{")]);
+        let request = GraphQLRequest::parse(b"", &query).unwrap();
+        assert_eq!(request.operation_name, None);
+        assert_eq!(request.variables, json!({}));
+    }
+
+    #[test]
+    fn missing_query_is_not_graphql() {
+        let body = json!({ "operationName": "GetUser" }).to_string();
+        assert!(GraphQLRequest::parse(body.as_bytes(), &HashMap::new()).is_none());
+        assert!(GraphQLRequest::parse(b"", &params(&[("operationName", "Me")])).is_none());
+        assert!(GraphQLRequest::parse(b"not json", &HashMap::new()).is_none());
+    }
+
+    #[test]
+    fn explicit_operation_name_wins_over_the_document() {
+        let body = json!({
+            "query": "query First { a } mutation Second { b }",
+            "operationName": "Second"
+        });
+        let request = GraphQLRequest::parse(body.to_string().as_bytes(), &HashMap::new()).unwrap();
+        assert_eq!(request.operation_name.as_deref(), Some("Second"));
+
+        let body = json!({ "query": "mutation  AddItem { add }" });
+        let request = GraphQLRequest::parse(body.to_string().as_bytes(), &HashMap::new()).unwrap();
+        assert_eq!(request.operation_name.as_deref(), Some("AddItem"));
+    }
+
+    #[test]
+    fn envelope_wraps_data_and_errors() {
+        assert_eq!(
+            envelope(r#"{"user": {"id": 1}}"#, None).unwrap(),
+            r#"{"data":{"user":{"id":1}}}"#
+        );
+        assert_eq!(
+            envelope("  ", Some(r#"[{"message": "not found"}]"#)).unwrap(),
+            r#"{"data":null,"errors":[{"message":"not found"}]}"#
+        );
+        assert!(envelope("{", None)
+            .unwrap_err()
+            .starts_with("GraphQL data is not valid JSON"));
+        assert!(envelope("{}", Some("oops"))
+            .unwrap_err()
+            .starts_with("GraphQL errors are not valid JSON"));
+    }
+}
@@ -0,0 +1,472 @@
+// src/grpc.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use h2::server::{self, SendResponse};
+use h2::RecvStream;
+use http::{HeaderMap, HeaderName, HeaderValue, Request, Response};
+use log::{error, info};
+use prost_reflect::bytes::{Buf, BufMut, Bytes, BytesMut};
+use prost_reflect::prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, MethodDescriptor, SerializeOptions};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+use crate::matchers::RequestView;
+use crate::models::{GrpcMethodInfo, GrpcSpec, MethodSpec};
+use crate::router::ANY_METHOD;
+use crate::state::{body_template_name, header_template_name, AppState};
+
+// gRPC status codes returned by the listener itself
+const OK: u32 = 0;
+const INVALID_ARGUMENT: u32 = 3;
+const RESOURCE_EXHAUSTED: u32 = 8;
+const UNIMPLEMENTED: u32 = 12;
+const INTERNAL: u32 = 13;
+const MAX_STATUS_CODE: u32 = 16;
+
+// Largest request message accepted, the usual gRPC default
+const MAX_REQUEST_BYTES: usize = 4 * 1024 * 1024;
+
+impl GrpcSpec {
+    /// Check the status code, that the api_name names a `package.Service/Method` and that
+    /// the mock answers POST, the only method gRPC calls are looked up under
+    pub fn validate(&self, api_name: &str, method: &MethodSpec) -> Result<(), String> {
+        let methods = method.methods();
+        if !methods.iter().any(|m| m == "POST" || m == ANY_METHOD) {
+            return Err("gRPC mocks must use the POST or ANY method".to_string());
+        }
+        if self.code > MAX_STATUS_CODE {
+            return Err(format!(
+                "grpc.code must be a gRPC status code between 0 and {}",
+                MAX_STATUS_CODE
+            ));
+        }
+        match api_name.split_once('/') {
+            Some((service, method))
+                if service.contains('.') && !method.is_empty() && !method.contains('/') =>
+            {
+                Ok(())
+            }
+            _ => Err("gRPC mocks need an api_name of the form package.Service/Method".to_string()),
+        }
+    }
+}
+
+/// Look up a `package.Service/Method` in the uploaded descriptors
+pub fn find_method(pool: &DescriptorPool, name: &str) -> Option<MethodDescriptor> {
+    let (service, method) = name.split_once('/')?;
+    pool.get_service_by_name(service)?
+        .methods()
+        .find(|m| m.name() == method)
+}
+
+/// Every method of every service in the uploaded descriptors
+pub fn list_methods(pool: &DescriptorPool) -> Vec<GrpcMethodInfo> {
+    pool.services()
+        .flat_map(|service| service.methods().collect::<Vec<_>>())
+        .map(|method| GrpcMethodInfo {
+            name: format!("{}/{}", method.parent_service().full_name(), method.name()),
+            input: method.input().full_name().to_string(),
+            output: method.output().full_name().to_string(),
+            client_streaming: method.is_client_streaming(),
+            server_streaming: method.is_server_streaming(),
+        })
+        .collect()
+}
+
+/// Serve gRPC mocks over cleartext HTTP/2 (prior knowledge) on a second listener
+pub async fn serve(state: Arc<AppState>, listener: TcpListener) {
+    loop {
+        let (socket, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                error!("gRPC listener failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut connection = match server::handshake(socket).await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    error!("HTTP/2 handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            while let Some(call) = connection.accept().await {
+                match call {
+                    Ok((request, respond)) => {
+                        tokio::spawn(handle_call(state.clone(), peer.ip(), request, respond));
+                    }
+                    Err(e) => {
+                        error!("gRPC connection with {} failed: {}", peer, e);
+                        break;
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Response to a unary call: metadata, an optional message and the trailing status
+struct GrpcReply {
+    headers: Vec<(String, String)>,
+    message: Option<Vec<u8>>,
+    code: u32,
+    status_message: Option<String>,
+}
+
+impl GrpcReply {
+    fn status(code: u32, message: impl Into<String>) -> Self {
+        GrpcReply {
+            headers: Vec::new(),
+            message: None,
+            code,
+            status_message: Some(message.into()),
+        }
+    }
+}
+
+async fn handle_call(
+    state: Arc<AppState>,
+    peer: IpAddr,
+    request: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+) {
+    let (parts, mut body) = request.into_parts();
+    let api_name = parts.uri.path().trim_start_matches('/').to_string();
+
+    let mut payload = BytesMut::new();
+    let mut too_large = false;
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) => {
+                let _ = body.flow_control().release_capacity(chunk.len());
+                if payload.len() + chunk.len() > MAX_REQUEST_BYTES + 5 {
+                    too_large = true;
+                    break;
+                }
+                payload.extend_from_slice(&chunk);
+            }
+            Err(e) => {
+                error!("Failed to read gRPC request for {}: {}", api_name, e);
+                return;
+            }
+        }
+    }
+
+    let reply = if too_large {
+        GrpcReply::status(
+            RESOURCE_EXHAUSTED,
+            format!("Request message is larger than {} bytes", MAX_REQUEST_BYTES),
+        )
+    } else {
+        answer(&state, peer, &api_name, &parts.headers, payload.freeze())
+            .await
+            .unwrap_or_else(|reply| reply)
+    };
+    if reply.code != OK {
+        info!("gRPC call {} answered with status {}", api_name, reply.code);
+    }
+
+    let mut response = Response::builder()
+        .status(200)
+        .header("content-type", "application/grpc");
+    for (name, value) in &reply.headers {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(value)) => response = response.header(name, value),
+            _ => error!(
+                "Rendered value of header {} is not a valid metadata value",
+                name
+            ),
+        }
+    }
+    let response = match response.body(()) {
+        Ok(response) => response,
+        Err(e) => {
+            error!("Failed to build gRPC response for {}: {}", api_name, e);
+            return;
+        }
+    };
+
+    let mut stream = match respond.send_response(response, false) {
+        Ok(stream) => stream,
+        Err(e) => {
+            error!("Failed to send gRPC response for {}: {}", api_name, e);
+            return;
+        }
+    };
+    if let Some(message) = reply.message {
+        let mut frame = BytesMut::with_capacity(message.len() + 5);
+        frame.put_u8(0); // Uncompressed
+        frame.put_u32(message.len() as u32);
+        frame.extend_from_slice(&message);
+        if let Err(e) = stream.send_data(frame.freeze(), false) {
+            error!("Failed to send gRPC message for {}: {}", api_name, e);
+            return;
+        }
+    }
+
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from(reply.code));
+    if let Some(message) = reply.status_message {
+        if let Ok(value) = HeaderValue::from_str(&percent_encode(&message)) {
+            trailers.insert("grpc-message", value);
+        }
+    }
+    if let Err(e) = stream.send_trailers(trailers) {
+        error!("Failed to send gRPC trailers for {}: {}", api_name, e);
+    }
+}
+
+/// Select and render the mock for a unary call
+async fn answer(
+    state: &AppState,
+    peer: IpAddr,
+    api_name: &str,
+    metadata: &HeaderMap,
+    payload: Bytes,
+) -> Result<GrpcReply, GrpcReply> {
+    let method =
+        find_method(&state.grpc_descriptors.read().unwrap(), api_name).ok_or_else(|| {
+            GrpcReply::status(
+                UNIMPLEMENTED,
+                format!("Method {} is not in the uploaded descriptors", api_name),
+            )
+        })?;
+
+    // Decode the request message so matchers and templates can use its fields
+    let request = DynamicMessage::decode(method.input(), unframe(payload)?).map_err(|e| {
+        GrpcReply::status(INVALID_ARGUMENT, format!("Invalid request message: {}", e))
+    })?;
+    let json_body = request
+        .serialize_with_options(
+            serde_json::value::Serializer,
+            &SerializeOptions::new().use_proto_field_name(true),
+        )
+        .map_err(|e| GrpcReply::status(INTERNAL, e.to_string()))?;
+
+    let headers: HashMap<String, String> = metadata
+        .iter()
+        .filter_map(|(key, value)| {
+            value
+                .to_str()
+                .ok()
+                .map(|v| (key.as_str().to_ascii_lowercase(), v.to_string()))
+        })
+        .collect();
+    let view = RequestView {
+        protocol: "HTTP/2".to_string(),
+        headers: headers.clone(),
+        query: HashMap::new(),
+        body: Some(json_body.clone()),
+    };
+
+    let candidates = state.route_candidates("POST", api_name);
+    let mut rate_decision = None;
+    let (mock_id, path_params, mock, step) = state
+        .select_variant(
+            candidates,
+            |mock| mock.grpc.is_some() && mock.matchers.as_ref().is_none_or(|m| m.matches(&view)),
+            |mock_id, mock| match &mock.rate_limit {
+                Some(spec) => {
+                    let key = spec.grpc_client_key(peer, &headers);
+                    let decision = state.check_rate_limit(mock_id, spec, key);
+                    let allowed = decision.allowed;
+                    rate_decision = Some(decision);
+                    allowed
+                }
+                None => true,
+            },
+        )
+        .ok_or_else(|| {
+            GrpcReply::status(
+                UNIMPLEMENTED,
+                format!("No mock variant matched the call to {}", api_name),
+            )
+        })?;
+
+    // Calls beyond the mock's rate limit are refused with the rate limit metadata
+    let rate_headers: Vec<(String, String)> = rate_decision
+        .iter()
+        .flat_map(|decision| decision.headers())
+        .map(|(name, value)| (name.to_ascii_lowercase(), value))
+        .collect();
+    if rate_decision
+        .as_ref()
+        .is_some_and(|decision| !decision.allowed)
+    {
+        info!("Rate limit of mock {} exceeded for {}", mock_id, api_name);
+        let mut reply = GrpcReply::status(RESOURCE_EXHAUSTED, "Rate limit exceeded");
+        reply.headers = rate_headers;
+        return Err(reply);
+    }
+
+    // Templates see the metadata and the request message's fields, which win over the
+    // built-in values of the same name. `path` always holds the captured parameters.
+    let mut data = Map::new();
+    data.insert("api_name".to_string(), Value::String(api_name.to_string()));
+    data.insert("protocol".to_string(), Value::String("HTTP/2".to_string()));
+    for (key, value) in headers {
+        data.insert(key, Value::String(value));
+    }
+    if let Value::Object(fields) = json_body {
+        data.extend(fields);
+    }
+    data.insert("path".to_string(), Value::Object(path_params));
+
+    // Step headers are merged over the mock-level headers, as for HTTP mocks
+    let mut header_names: Vec<(String, String)> = mock
+        .headers
+        .keys()
+        .filter(|name| {
+            step.is_none_or(|i| {
+                !mock.responses[i]
+                    .headers
+                    .keys()
+                    .any(|own| own.eq_ignore_ascii_case(name))
+            })
+        })
+        .map(|name| (name.clone(), header_template_name(mock_id, None, name)))
+        .collect();
+    if let Some(i) = step {
+        header_names.extend(
+            mock.responses[i]
+                .headers
+                .keys()
+                .map(|name| (name.clone(), header_template_name(mock_id, step, name))),
+        );
+    }
+
+    let (rendered, mut rendered_headers) = {
+        let handlebars = state.handlebars.lock().unwrap();
+        let rendered = handlebars
+            .render(&body_template_name(mock_id, step), &data)
+            .map_err(|e| GrpcReply::status(INTERNAL, format!("Template rendering error: {}", e)))?;
+        let mut rendered_headers = Vec::with_capacity(header_names.len());
+        for (name, template) in header_names {
+            let value = handlebars.render(&template, &data).map_err(|e| {
+                GrpcReply::status(INTERNAL, format!("Template rendering error: {}", e))
+            })?;
+            rendered_headers.push((name.to_ascii_lowercase(), value));
+        }
+        (rendered, rendered_headers)
+    };
+    rendered_headers.extend(rate_headers);
+
+    // Introduce delay if specified, scaled by the global multiplier
+    let delay = match step {
+        Some(i) => &mock.responses[i].delay,
+        None => &mock.delay,
+    };
+    let delay = state.scale_delay(delay.sample());
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+
+    let spec = mock.grpc.unwrap_or_default();
+    if spec.code != OK {
+        return Ok(GrpcReply {
+            headers: rendered_headers,
+            message: None,
+            code: spec.code,
+            status_message: spec.message,
+        });
+    }
+
+    // Encode the rendered JSON as the method's output message
+    let output = method.output();
+    let json = if rendered.trim().is_empty() {
+        "{}"
+    } else {
+        rendered.as_str()
+    };
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    let message = DynamicMessage::deserialize(output.clone(), &mut deserializer)
+        .and_then(|message| deserializer.end().map(|_| message))
+        .map_err(|e| {
+            GrpcReply::status(
+                INTERNAL,
+                format!("Mock response is not a valid {}: {}", output.full_name(), e),
+            )
+        })?;
+
+    Ok(GrpcReply {
+        headers: rendered_headers,
+        message: Some(message.encode_to_vec()),
+        code: OK,
+        status_message: None,
+    })
+}
+
+/// Extract the single message of a unary request from its length-prefixed frame
+fn unframe(mut payload: Bytes) -> Result<Bytes, GrpcReply> {
+    if payload.len() < 5 {
+        return Err(GrpcReply::status(
+            INVALID_ARGUMENT,
+            "Malformed gRPC message frame",
+        ));
+    }
+    let compressed = payload.get_u8() != 0;
+    let length = payload.get_u32() as usize;
+    if compressed {
+        return Err(GrpcReply::status(
+            UNIMPLEMENTED,
+            "Compressed messages are not supported",
+        ));
+    }
+    if payload.len() < length {
+        return Err(GrpcReply::status(
+            INVALID_ARGUMENT,
+            "Truncated gRPC message frame",
+        ));
+    }
+    if payload.len() > length {
+        return Err(GrpcReply::status(
+            UNIMPLEMENTED,
+            "Only unary calls with a single request message are supported",
+        ));
+    }
+    Ok(payload)
+}
+
+/// Percent-encode a grpc-message value as required by the gRPC HTTP/2 protocol
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        if (0x20..0x7f).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mocks_must_be_reachable_through_post() {
+        let spec = GrpcSpec::default();
+        let api_name = "shop.Orders/Get";
+        let one = |m: &str| MethodSpec::One(m.to_string());
+
+        assert!(spec.validate(api_name, &one("post")).is_ok());
+        assert!(spec.validate(api_name, &one("ANY")).is_ok());
+        let many = MethodSpec::Many(vec!["GET".to_string(), "POST".to_string()]);
+        assert!(spec.validate(api_name, &many).is_ok());
+        assert!(spec.validate(api_name, &one("GET")).is_err());
+        assert!(spec.validate("orders", &one("POST")).is_err());
+    }
+}
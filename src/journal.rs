@@ -0,0 +1,299 @@
+// src/journal.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use actix_web::HttpRequest;
+use chrono::Utc;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Mutex, RwLock};
+use uuid::Uuid;
+
+use crate::matchers::{compiled_regex, protocol_name, RequestView};
+use crate::models::{JournalConfig, JournalEntry, JournalFilter};
+
+impl JournalConfig {
+    /// Override the journal limits with `JOURNAL_MAX_ENTRIES` and `JOURNAL_MAX_BODY_BYTES`
+    pub fn apply_env(&mut self) -> Result<(), String> {
+        if let Ok(value) = std::env::var("JOURNAL_MAX_ENTRIES") {
+            self.max_entries = value
+                .parse()
+                .map_err(|_| format!("JOURNAL_MAX_ENTRIES must be a number, got '{}'", value))?;
+        }
+        if let Ok(value) = std::env::var("JOURNAL_MAX_BODY_BYTES") {
+            self.max_body_bytes = value
+                .parse()
+                .map_err(|_| format!("JOURNAL_MAX_BODY_BYTES must be a number, got '{}'", value))?;
+        }
+        Ok(())
+    }
+}
+
+impl JournalEntry {
+    /// Capture a request as it is received; the mock id and status are filled in once served
+    pub fn new(
+        req: &HttpRequest,
+        api_name: &str,
+        query: &HashMap<String, String>,
+        body: &[u8],
+        max_body_bytes: usize,
+    ) -> Self {
+        let headers = req
+            .headers()
+            .iter()
+            .filter_map(|(key, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|v| (key.as_str().to_ascii_lowercase(), v.to_string()))
+            })
+            .collect();
+
+        let kept = &body[..body.len().min(max_body_bytes)];
+        JournalEntry {
+            id: Uuid::new_v4(),
+            timestamp: Utc::now(),
+            method: req.method().to_string(),
+            protocol: protocol_name(req.version()).to_string(),
+            path: api_name.to_string(),
+            headers,
+            query: query
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect::<BTreeMap<_, _>>(),
+            body: String::from_utf8_lossy(kept).into_owned(),
+            body_truncated: kept.len() < body.len(),
+            mock_id: None,
+            status: 0,
+        }
+    }
+}
+
+impl RequestView {
+    /// View of a journaled request, for matching it after the fact
+    pub fn from_entry(entry: &JournalEntry) -> Self {
+        RequestView {
+            protocol: entry.protocol.clone(),
+            headers: entry.headers.clone().into_iter().collect(),
+            query: entry.query.clone().into_iter().collect(),
+            body: serde_json::from_str(&entry.body).ok(),
+        }
+    }
+}
+
+impl JournalFilter {
+    /// Check that the path filter is a valid regex
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(pattern) = &self.path {
+            compiled_regex(pattern).map_err(|e| format!("path filter '{}': {}", pattern, e))?;
+        }
+        Ok(())
+    }
+
+    fn accepts(&self, entry: &JournalEntry) -> bool {
+        self.method
+            .as_ref()
+            .is_none_or(|m| m.eq_ignore_ascii_case(&entry.method))
+            && self
+                .protocol
+                .as_ref()
+                .is_none_or(|p| p.eq_ignore_ascii_case(&entry.protocol))
+            && self.path.as_ref().is_none_or(|pattern| {
+                compiled_regex(pattern)
+                    .map(|regex| regex.is_match(&entry.path))
+                    .unwrap_or(false)
+            })
+            && self.mock_id.is_none_or(|id| entry.mock_id == Some(id))
+            && self
+                .matched
+                .is_none_or(|matched| entry.mock_id.is_some() == matched)
+            && self.status.is_none_or(|status| entry.status == status)
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+    }
+}
+
+/// Bounded, in-memory log of the requests received on mock paths
+pub struct Journal {
+    entries: Mutex<VecDeque<JournalEntry>>, // Oldest first
+    config: RwLock<JournalConfig>,
+}
+
+impl Journal {
+    pub fn new(config: JournalConfig) -> Self {
+        Journal {
+            entries: Mutex::new(VecDeque::new()),
+            config: RwLock::new(config),
+        }
+    }
+
+    pub fn config(&self) -> JournalConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Replace the limits, dropping the oldest entries beyond the new size
+    pub fn set_config(&self, config: JournalConfig) {
+        let mut entries = self.entries.lock().unwrap();
+        while entries.len() > config.max_entries {
+            entries.pop_front();
+        }
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Body size cap applied to new entries
+    pub fn max_body_bytes(&self) -> usize {
+        self.config.read().unwrap().max_body_bytes
+    }
+
+    pub fn record(&self, entry: JournalEntry) {
+        let max_entries = self.config.read().unwrap().max_entries;
+        if max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        while entries.len() >= max_entries {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Entries accepted by the filter, oldest first
+    pub fn entries(&self, filter: &JournalFilter) -> Vec<JournalEntry> {
+        let entries = self.entries.lock().unwrap();
+        let mut matching: Vec<JournalEntry> = entries
+            .iter()
+            .filter(|entry| filter.accepts(entry))
+            .cloned()
+            .collect();
+        if let Some(limit) = filter.limit {
+            matching.drain(..matching.len().saturating_sub(limit));
+        }
+        matching
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Duration};
+
+    fn entry(method: &str, path: &str, status: u16, at: DateTime<Utc>) -> JournalEntry {
+        JournalEntry {
+            method: method.to_string(),
+            path: path.to_string(),
+            protocol: "HTTP/1.1".to_string(),
+            status,
+            timestamp: at,
+            ..Default::default()
+        }
+    }
+
+    fn journal(max_entries: usize) -> Journal {
+        Journal::new(JournalConfig {
+            max_entries,
+            ..Default::default()
+        })
+    }
+
+    fn paths(entries: Vec<JournalEntry>) -> Vec<String> {
+        entries.into_iter().map(|e| e.path).collect()
+    }
+
+    #[test]
+    fn filters_on_method_path_status_and_time() {
+        let start = Utc::now();
+        let served = Uuid::new_v4();
+        let journal = journal(10);
+        journal.record(entry("GET", "users/1", 200, start));
+        journal.record(JournalEntry {
+            mock_id: Some(served),
+            ..entry("POST", "users", 201, start + Duration::seconds(1))
+        });
+        journal.record(entry("get", "orders/1", 404, start + Duration::seconds(2)));
+
+        let filter = |filter: JournalFilter| paths(journal.entries(&filter));
+        assert_eq!(
+            filter(JournalFilter {
+                method: Some("GET".to_string()),
+                ..Default::default()
+            }),
+            ["users/1", "orders/1"]
+        );
+        assert_eq!(
+            filter(JournalFilter {
+                path: Some("^users".to_string()),
+                ..Default::default()
+            }),
+            ["users/1", "users"]
+        );
+        assert_eq!(
+            filter(JournalFilter {
+                status: Some(404),
+                ..Default::default()
+            }),
+            ["orders/1"]
+        );
+        assert_eq!(
+            filter(JournalFilter {
+                since: Some(start + Duration::seconds(1)),
+                ..Default::default()
+            }),
+            ["users", "orders/1"]
+        );
+        assert_eq!(
+            filter(JournalFilter {
+                matched: Some(true),
+                ..Default::default()
+            }),
+            ["users"]
+        );
+        assert_eq!(
+            filter(JournalFilter {
+                mock_id: Some(served),
+                method: Some("GET".to_string()),
+                ..Default::default()
+            }),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            filter(JournalFilter {
+                method: Some("GET".to_string()),
+                protocol: Some("http/1.1".to_string()),
+                limit: Some(1),
+                ..Default::default()
+            }),
+            ["orders/1"]
+        );
+    }
+
+    #[test]
+    fn invalid_path_filters_are_rejected() {
+        let filter = JournalFilter {
+            path: Some("(".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.validate().is_err());
+        assert!(!filter.accepts(&entry("GET", "(", 200, Utc::now())));
+    }
+
+    #[test]
+    fn oldest_entries_are_dropped() {
+        let journal = journal(2);
+        for path in ["a", "b", "c"] {
+            journal.record(entry("GET", path, 200, Utc::now()));
+        }
+        assert_eq!(
+            paths(journal.entries(&JournalFilter::default())),
+            ["b", "c"]
+        );
+
+        journal.set_config(JournalConfig {
+            max_entries: 1,
+            ..Default::default()
+        });
+        assert_eq!(paths(journal.entries(&JournalFilter::default())), ["c"]);
+    }
+}
@@ -3,10 +3,29 @@
 // Author: Md Hasan Basri
 // Email: pothiq@gmail.com
 
+mod callbacks;
+mod config;
+mod cors;
+mod delays;
+mod diagnostics;
+mod faults;
+mod graphql;
+mod grpc;
+mod journal;
+mod matchers;
 mod models;
+mod proxy;
+mod ratelimit;
+mod recorder;
+mod router;
 mod routes;
+mod sse;
 mod state;
+mod throttle;
+mod tls;
 mod utils;
+mod verify;
+mod websocket;
 
 // Conditionally include the metrics module
 #[cfg(feature = "metrics")]
@@ -15,30 +34,37 @@ mod metrics;
 // Conditionally import metrics_handler and MetricsMiddleware
 #[cfg(feature = "metrics")]
 use crate::metrics::{metrics_handler, MetricsMiddleware};
+#[cfg(feature = "metrics")]
+use actix_web::middleware::Condition;
 
+use crate::config::Cli;
+use crate::models::Settings;
 use crate::routes::{
-    delete_all_mocks, delete_all_mocks_internal, delete_mock, delete_mock_internal, get_mock,
-    handle_mock, health_check, list_mocks, readiness_check, save_mock, save_mock_internal,
-    update_mock, update_mock_internal,
+    clear_callbacks, clear_requests, close_ws_session, delete_all_mocks, delete_all_mocks_internal,
+    delete_mock, delete_mock_internal, get_cors_config, get_delay_multiplier, get_journal_config,
+    get_mock, get_near_miss_config, get_proxy_config, get_server_config, get_tls_ca, handle_mock,
+    health_check, issue_client_cert, list_callbacks, list_grpc_methods, list_mocks,
+    list_near_misses, list_requests, list_scenarios, list_ws_sessions, readiness_check,
+    recording_status, reset_all_rate_limits, reset_all_scenarios, reset_all_sequences,
+    reset_rate_limit, reset_scenario, reset_sequence, save_mock, save_mock_internal,
+    send_ws_message, set_cors_config, set_delay_multiplier, set_journal_config,
+    set_near_miss_config, set_proxy_config, set_scenario_state, start_recording, stop_recording,
+    update_mock, update_mock_internal, upload_descriptors, upload_descriptors_internal,
+    verify_requests, ws_mock,
 };
 use crate::state::AppState;
-use actix_web::{middleware::Compress, web, App, HttpRequest, HttpResponse, HttpServer};
-use dashmap::DashMap;
+use actix_web::middleware::{from_fn, Compress};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use clap::Parser;
 use env_logger::Env;
-use handlebars::Handlebars;
 use log::{error, info};
 use rust_embed::RustEmbed;
-use std::env;
 use std::io::Write;
 use std::panic;
-use std::sync::atomic::AtomicUsize;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use tokio::time::{interval, sleep, Duration};
 use utils::get_other_pod_ips;
 
-#[cfg(all(feature = "metrics", target_os = "linux"))]
-use prometheus::process_collector::ProcessCollector;
-
 #[derive(RustEmbed)]
 #[folder = "static/"]
 struct StaticFiles;
@@ -56,7 +82,7 @@ async fn static_files(req: HttpRequest) -> HttpResponse {
     let filename: &str = req.match_info().query("filename");
     match StaticFiles::get(filename) {
         Some(content) => {
-            let content_type = match filename.split('.').last() {
+            let content_type = match filename.rsplit('.').next() {
                 Some("css") => "text/css",
                 Some("js") => "application/javascript",
                 Some("html") => "text/html",
@@ -80,12 +106,12 @@ async fn discover_and_sync_peers(app_data: Arc<AppState>) {
     sleep(Duration::from_secs(2)).await;
 
     // Periodically sync with peers
-    let mut sync_interval = interval(Duration::from_secs(60)); // Every 60 seconds
+    let mut sync_interval = interval(Duration::from_secs(app_data.config.sync.interval_secs));
 
     loop {
         sync_interval.tick().await;
 
-        let peer_pod_ips = match get_other_pod_ips().await {
+        let peer_pod_ips = match get_other_pod_ips(&app_data.config.sync).await {
             Ok(ips) => ips,
             Err(e) => {
                 eprintln!("Failed to get other pod IPs: {}", e);
@@ -114,6 +140,7 @@ async fn discover_and_sync_peers(app_data: Arc<AppState>) {
 }
 
 async fn run_server() -> std::io::Result<()> {
+    let cli = Cli::parse();
     env_logger::init_from_env(Env::default().default_filter_or("info"));
 
     panic::set_hook(Box::new(|panic_info| {
@@ -123,28 +150,68 @@ async fn run_server() -> std::io::Result<()> {
     info!("Application is starting...");
     std::io::stdout().flush().unwrap();
 
-    // Initialize Handlebars and register helpers
-    let mut handlebars = Handlebars::new();
-    utils::register_helpers(&mut handlebars);
+    // Effective configuration: defaults, then the config file, then environment and flags
+    let settings = Settings::load(&cli)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    match toml::to_string(&settings.redacted()) {
+        Ok(printed) => info!("Effective configuration:\n{}", printed),
+        Err(e) => error!("Failed to print the configuration: {}", e),
+    }
 
-    // Read environment variables for metrics
-    let metrics_enabled = env::var("METRICS_ENABLED")
-        .unwrap_or_else(|_| "false".to_string())
-        .parse::<bool>()
-        .unwrap_or(false);
+    #[cfg(feature = "metrics")]
+    let metrics_enabled = settings.metrics.enabled;
 
     #[cfg(feature = "metrics")]
-    let metrics_port = env::var("METRICS_PORT")
-        .unwrap_or_else(|_| "9090".to_string())
-        .parse::<u16>()
-        .unwrap_or(9090);
-
-    let app_data = Arc::new(AppState {
-        mocks: DashMap::new(),
-        api_name_to_id: DashMap::new(),
-        handlebars: Arc::new(Mutex::new(handlebars)),
-        synced_peers: AtomicUsize::new(0),
-    });
+    let metrics_port = settings.metrics.port;
+
+    let host = settings.server.host.clone();
+    let grpc_port = settings.server.grpc_port;
+
+    // Fallback upstreams for requests that match no mock
+    let proxy = &settings.proxy;
+    if let Some(upstream) = &proxy.upstream {
+        info!("Unmatched requests are forwarded to {}", upstream);
+    }
+    for (prefix, upstream) in &proxy.prefixes {
+        info!(
+            "Unmatched requests under '{}' are forwarded to {}",
+            prefix, upstream
+        );
+    }
+
+    // Near-miss diagnostics for unmatched requests
+    if settings.near_miss.enabled {
+        info!("Near-miss diagnostics are enabled");
+    }
+
+    // Cross-origin policy for browser clients
+    let cors = &settings.cors;
+    if cors.enabled {
+        info!(
+            "CORS is enabled for origins {}",
+            cors.allowed_origins.join(", ")
+        );
+    }
+
+    // HTTPS listener, with a supplied certificate or one signed by a generated CA
+    let tls = settings.tls.clone();
+    let (tls_server_config, tls_ca) = match &tls {
+        Some(tls) => {
+            let (server_config, ca) = tls
+                .server_config()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            (Some(server_config), ca)
+        }
+        None => (None, None),
+    };
+
+    // Redirects are passed back to the caller instead of being followed
+    let http_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(std::io::Error::other)?;
+
+    let app_data = Arc::new(AppState::new(settings.clone(), http_client, tls_ca));
 
     // Start the peer discovery and synchronization in the background
     {
@@ -154,20 +221,21 @@ async fn run_server() -> std::io::Result<()> {
         });
     }
 
-    // Register process metrics collector if metrics are enabled
-    #[cfg(all(feature = "metrics", target_os = "linux"))]
-    {
-        if metrics_enabled {
-            prometheus::default_registry()
-                .register(Box::new(ProcessCollector::for_self()))
-                .unwrap();
-        }
+    // Drop the rate limit buckets of clients that went idle
+    tokio::spawn(ratelimit::prune_idle_buckets(app_data.clone()));
+
+    // Serve gRPC mocks on a second, HTTP/2-only listener sharing the same state
+    if settings.server.grpc_enabled {
+        let grpc_listener = tokio::net::TcpListener::bind((host.as_str(), grpc_port)).await?;
+        info!("gRPC mocks are served on port {}", grpc_port);
+        tokio::spawn(grpc::serve(app_data.clone(), grpc_listener));
     }
 
     // Initialize the HttpServer with conditional middleware wrapping
     let server = HttpServer::new(move || {
         let app = App::new()
             .wrap(Compress::default()) // Enable gzip compression
+            .wrap(from_fn(cors::cors)) // Answer preflights and add CORS headers
             .app_data(web::Data::from(app_data.clone()))
             .service(save_mock)
             .service(list_mocks)
@@ -179,8 +247,45 @@ async fn run_server() -> std::io::Result<()> {
             .service(delete_mock_internal)
             .service(delete_all_mocks)
             .service(delete_all_mocks_internal)
+            .service(reset_sequence)
+            .service(reset_all_sequences)
+            .service(reset_rate_limit)
+            .service(reset_all_rate_limits)
+            .service(list_scenarios)
+            .service(set_scenario_state)
+            .service(reset_scenario)
+            .service(reset_all_scenarios)
+            .service(get_proxy_config)
+            .service(set_proxy_config)
+            .service(start_recording)
+            .service(stop_recording)
+            .service(recording_status)
+            .service(get_delay_multiplier)
+            .service(set_delay_multiplier)
             .service(health_check)
             .service(readiness_check)
+            .service(list_ws_sessions)
+            .service(send_ws_message)
+            .service(close_ws_session)
+            .service(upload_descriptors)
+            .service(upload_descriptors_internal)
+            .service(list_grpc_methods)
+            .service(list_requests)
+            .service(clear_requests)
+            .service(verify_requests)
+            .service(list_near_misses)
+            .service(get_near_miss_config)
+            .service(set_near_miss_config)
+            .service(list_callbacks)
+            .service(clear_callbacks)
+            .service(get_tls_ca)
+            .service(issue_client_cert)
+            .service(get_cors_config)
+            .service(set_cors_config)
+            .service(get_journal_config)
+            .service(set_journal_config)
+            .service(get_server_config)
+            .service(ws_mock) // WebSocket upgrades, guarded so it runs before handle_mock
             .service(handle_mock) // Register the handler with attribute macro
             .route("/", web::get().to(index))
             .route("/static/{filename:.*}", web::get().to(static_files));
@@ -188,11 +293,7 @@ async fn run_server() -> std::io::Result<()> {
         // Conditionally apply MetricsMiddleware
         #[cfg(feature = "metrics")]
         {
-            if metrics_enabled {
-                app.wrap(MetricsMiddleware)
-            } else {
-                app
-            }
+            app.wrap(Condition::new(metrics_enabled, MetricsMiddleware))
         }
 
         #[cfg(not(feature = "metrics"))]
@@ -201,10 +302,23 @@ async fn run_server() -> std::io::Result<()> {
             app
         }
     })
-    .workers(num_cpus::get())
-    .max_connections(20_000)
-    .backlog(1024)
-    .bind("0.0.0.0:8080")?
+    .on_connect(faults::remember_connection) // Connection-level faults need the socket
+    .workers(settings.workers())
+    .max_connections(settings.server.max_connections)
+    .backlog(settings.server.backlog)
+    .bind_auto_h2c((host.as_str(), settings.server.port))?; // HTTP/1.x, or HTTP/2 with prior knowledge (h2c)
+
+    // Serve the same routes over HTTPS when configured
+    let server = match (tls, tls_server_config) {
+        (Some(tls), Some(server_config)) => {
+            info!(
+                "HTTPS is served on port {} (client certificates: {:?})",
+                tls.port, tls.client_auth
+            );
+            server.bind_rustls_0_23((host.as_str(), tls.port), server_config)?
+        }
+        _ => server,
+    }
     .run();
 
     // Start metrics server if enabled
@@ -214,7 +328,7 @@ async fn run_server() -> std::io::Result<()> {
             let metrics_server = HttpServer::new(move || {
                 App::new().route("/metrics", web::get().to(metrics_handler))
             })
-            .bind(("0.0.0.0", metrics_port))?
+            .bind((host.as_str(), metrics_port))?
             .run();
 
             // Start the metrics server in a separate task
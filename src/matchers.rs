@@ -0,0 +1,438 @@
+// src/matchers.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use actix_web::http::Version;
+use actix_web::HttpRequest;
+use jsonpath_lib::Compiled;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use crate::models::{JsonPathMatcher, RequestMatchers, ValueMatcher};
+
+// Compiled regexes and JSONPath expressions, keyed by their source text. Patterns also
+// come from ad-hoc journal and recording filters, so each cache is bounded.
+const MAX_CACHED_PATTERNS: usize = 1024;
+static REGEX_CACHE: OnceLock<Mutex<PatternCache<Regex>>> = OnceLock::new();
+static JSONPATH_CACHE: OnceLock<Mutex<PatternCache<Compiled>>> = OnceLock::new();
+
+/// Name of an HTTP version as journaled and matched, e.g. `HTTP/1.1` or `HTTP/2`
+pub fn protocol_name(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2",
+        Version::HTTP_3 => "HTTP/3",
+        _ => "HTTP/1.1",
+    }
+}
+
+/// The parts of an incoming request that matchers can inspect
+pub struct RequestView {
+    pub protocol: String,                 // HTTP version, e.g. `HTTP/2`
+    pub headers: HashMap<String, String>, // Header names are lower-cased
+    pub query: HashMap<String, String>,
+    pub body: Option<Value>, // Parsed JSON body, if the body is valid JSON
+}
+
+impl RequestView {
+    pub fn new(req: &HttpRequest, query: &HashMap<String, String>, body: &[u8]) -> Self {
+        let headers = req
+            .headers()
+            .iter()
+            .filter_map(|(key, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|v| (key.as_str().to_ascii_lowercase(), v.to_string()))
+            })
+            .collect();
+
+        let body = if body.is_empty() {
+            None
+        } else {
+            serde_json::from_slice(body).ok()
+        };
+
+        RequestView {
+            protocol: protocol_name(req.version()).to_string(),
+            headers,
+            query: query.clone(),
+            body,
+        }
+    }
+}
+
+impl RequestMatchers {
+    /// Returns true when no predicate is configured
+    pub fn is_empty(&self) -> bool {
+        self.protocol.is_none()
+            && self.headers.is_empty()
+            && self.query.is_empty()
+            && self.body.is_empty()
+    }
+
+    /// Check that every regex and JSONPath expression compiles
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, matcher) in self.headers.iter().chain(self.query.iter()) {
+            if let Some(pattern) = &matcher.matches {
+                compiled_regex(pattern).map_err(|e| format!("matcher '{}': {}", name, e))?;
+            }
+        }
+        if let Some(pattern) = self.protocol.as_ref().and_then(|m| m.matches.as_ref()) {
+            compiled_regex(pattern).map_err(|e| format!("protocol matcher: {}", e))?;
+        }
+        for matcher in &self.body {
+            matcher.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Returns true when the request satisfies every predicate
+    pub fn matches(&self, req: &RequestView) -> bool {
+        self.protocol
+            .as_ref()
+            .is_none_or(|m| m.check(Some(&req.protocol)).is_ok())
+            && self.headers.iter().all(|(name, m)| {
+                m.check(
+                    req.headers
+                        .get(&name.to_ascii_lowercase())
+                        .map(String::as_str),
+                )
+                .is_ok()
+            })
+            && self
+                .query
+                .iter()
+                .all(|(name, m)| m.check(req.query.get(name).map(String::as_str)).is_ok())
+            && self.body.iter().all(|m| m.check(req.body.as_ref()).is_ok())
+    }
+
+    /// Reasons the request fails the predicates, one per failing matcher; empty when it matches
+    pub fn mismatches(&self, req: &RequestView) -> Vec<String> {
+        let protocol = self.protocol.iter().filter_map(|m| {
+            m.check(Some(&req.protocol))
+                .err()
+                .map(|e| format!("protocol: {}", e))
+        });
+        let headers = self.headers.iter().filter_map(|(name, m)| {
+            m.check(
+                req.headers
+                    .get(&name.to_ascii_lowercase())
+                    .map(String::as_str),
+            )
+            .err()
+            .map(|e| format!("header {}: {}", name, e))
+        });
+        let query = self.query.iter().filter_map(|(name, m)| {
+            m.check(req.query.get(name).map(String::as_str))
+                .err()
+                .map(|e| format!("query {}: {}", name, e))
+        });
+        let body = self.body.iter().filter_map(|m| {
+            m.check(req.body.as_ref())
+                .err()
+                .map(|e| format!("body {}: {}", m.path, e))
+        });
+        protocol.chain(headers).chain(query).chain(body).collect()
+    }
+}
+
+impl ValueMatcher {
+    /// Check a single header or query value, returning the reason on failure
+    pub fn check(&self, actual: Option<&str>) -> Result<(), String> {
+        let actual = match (actual, self.present) {
+            (Some(v), Some(false)) => return Err(format!("expected absent, got '{}'", v)),
+            (None, Some(false)) => return Ok(()),
+            (None, _) => return Err("missing".to_string()),
+            (Some(v), _) => v,
+        };
+
+        if let Some(expected) = &self.equals {
+            if actual != expected {
+                return Err(format!("expected '{}', got '{}'", expected, actual));
+            }
+        }
+        if let Some(pattern) = &self.matches {
+            let regex = compiled_regex(pattern)?;
+            if !regex.is_match(actual) {
+                return Err(format!("'{}' does not match /{}/", actual, pattern));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl JsonPathMatcher {
+    /// Check that the JSONPath expression and regex compile
+    pub fn validate(&self) -> Result<(), String> {
+        compiled_jsonpath(&self.path)
+            .map_err(|e| format!("invalid JSONPath '{}': {}", self.path, e))?;
+        if let Some(pattern) = &self.matches {
+            compiled_regex(pattern).map_err(|e| format!("matcher '{}': {}", self.path, e))?;
+        }
+        Ok(())
+    }
+
+    /// Check the request body, returning the reason on failure
+    pub fn check(&self, body: Option<&Value>) -> Result<(), String> {
+        let selected = match body {
+            Some(json) => compiled_jsonpath(&self.path)?
+                .select(json)
+                .map_err(|e| format!("{:?}", e))?,
+            None => Vec::new(),
+        };
+
+        if self.present == Some(false) {
+            if selected.is_empty() {
+                return Ok(());
+            }
+            return Err("expected no value".to_string());
+        }
+        if selected.is_empty() {
+            return Err(match body {
+                Some(_) => "no value selected".to_string(),
+                None => "request body is not JSON".to_string(),
+            });
+        }
+
+        // At least one selected value must satisfy every condition
+        let mut last_error = String::new();
+        for value in &selected {
+            match self.check_value(value) {
+                Ok(()) => return Ok(()),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn check_value(&self, value: &Value) -> Result<(), String> {
+        if let Some(expected) = &self.equals {
+            if value != expected {
+                return Err(format!("expected {}, got {}", expected, value));
+            }
+        }
+        if let Some(pattern) = &self.matches {
+            let text = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            if !compiled_regex(pattern)?.is_match(&text) {
+                return Err(format!("'{}' does not match /{}/", text, pattern));
+            }
+        }
+
+        let bounds = [
+            (self.gt, ">"),
+            (self.gte, ">="),
+            (self.lt, "<"),
+            (self.lte, "<="),
+        ];
+        for (bound, op) in bounds {
+            let bound = match bound {
+                Some(bound) => bound,
+                None => continue,
+            };
+            let n = value
+                .as_f64()
+                .ok_or_else(|| format!("{} is not a number", value))?;
+            let ok = match op {
+                ">" => n > bound,
+                ">=" => n >= bound,
+                "<" => n < bound,
+                _ => n <= bound,
+            };
+            if !ok {
+                return Err(format!("expected {} {} {}", n, op, bound));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compile a regex once and reuse it for later requests
+pub fn compiled_regex(pattern: &str) -> Result<Regex, String> {
+    let cache = REGEX_CACHE.get_or_init(|| Mutex::new(PatternCache::new(MAX_CACHED_PATTERNS)));
+    if let Some(regex) = cache.lock().unwrap().get(pattern) {
+        return Ok(regex);
+    }
+    let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+    cache.lock().unwrap().insert(pattern, regex.clone());
+    Ok(regex)
+}
+
+fn compiled_jsonpath(path: &str) -> Result<Compiled, String> {
+    let cache = JSONPATH_CACHE.get_or_init(|| Mutex::new(PatternCache::new(MAX_CACHED_PATTERNS)));
+    if let Some(compiled) = cache.lock().unwrap().get(path) {
+        return Ok(compiled);
+    }
+    let compiled = Compiled::compile(path)?;
+    cache.lock().unwrap().insert(path, compiled.clone());
+    Ok(compiled)
+}
+
+/// Compiled patterns keyed by their source text, dropping the least recently used one
+/// once `capacity` is reached
+struct PatternCache<T> {
+    capacity: usize,
+    entries: HashMap<String, (T, u64)>, // Compiled pattern and the tick it was last used
+    tick: u64,
+}
+
+impl<T: Clone> PatternCache<T> {
+    fn new(capacity: usize) -> Self {
+        PatternCache {
+            capacity,
+            entries: HashMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, source: &str) -> Option<T> {
+        self.tick += 1;
+        let (compiled, used) = self.entries.get_mut(source)?;
+        *used = self.tick;
+        Some(compiled.clone())
+    }
+
+    fn insert(&mut self, source: &str, compiled: T) {
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(source) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.tick += 1;
+        self.entries
+            .insert(source.to_string(), (compiled, self.tick));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matchers(spec: Value) -> RequestMatchers {
+        serde_json::from_value(spec).unwrap()
+    }
+
+    fn view(headers: &[(&str, &str)], query: &[(&str, &str)], body: Option<Value>) -> RequestView {
+        let pairs = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        RequestView {
+            protocol: "HTTP/1.1".to_string(),
+            headers: pairs(headers),
+            query: pairs(query),
+            body,
+        }
+    }
+
+    #[test]
+    fn header_and_query_matchers() {
+        let m = matchers(json!({
+            "headers": { "X-Api-Key": { "equals": "secret" } },
+            "query": { "page": { "matches": "^[0-9]+$" } }
+        }));
+        let ok = view(&[("x-api-key", "secret")], &[("page", "2")], None);
+        assert!(m.matches(&ok));
+        assert!(m.mismatches(&ok).is_empty());
+
+        let bad = view(&[("x-api-key", "other")], &[("page", "two")], None);
+        assert!(!m.matches(&bad));
+        assert_eq!(
+            m.mismatches(&bad),
+            [
+                "header X-Api-Key: expected 'secret', got 'other'",
+                "query page: 'two' does not match /^[0-9]+$/",
+            ]
+        );
+
+        let missing = view(&[], &[("page", "2")], None);
+        assert_eq!(m.mismatches(&missing), ["header X-Api-Key: missing"]);
+    }
+
+    #[test]
+    fn present_false_requires_absence() {
+        let m = matchers(json!({
+            "headers": { "authorization": { "present": false } },
+            "body": [{ "path": "$.debug", "present": false }]
+        }));
+        assert!(m.matches(&view(&[], &[], Some(json!({ "user": 1 })))));
+        assert!(m.matches(&view(&[], &[], None)));
+
+        let present = view(
+            &[("authorization", "Bearer x")],
+            &[],
+            Some(json!({ "debug": true })),
+        );
+        assert!(!m.matches(&present));
+        assert_eq!(
+            m.mismatches(&present),
+            [
+                "header authorization: expected absent, got 'Bearer x'",
+                "body $.debug: expected no value",
+            ]
+        );
+    }
+
+    #[test]
+    fn body_matchers() {
+        let m = matchers(json!({
+            "body": [
+                { "path": "$.items[*].sku", "equals": "B" },
+                { "path": "$.total", "gte": 10, "lt": 100 }
+            ]
+        }));
+        let body = json!({ "items": [{ "sku": "A" }, { "sku": "B" }], "total": 10 });
+        assert!(m.matches(&view(&[], &[], Some(body))));
+
+        let body = json!({ "items": [{ "sku": "A" }], "total": 100 });
+        assert_eq!(
+            m.mismatches(&view(&[], &[], Some(body))),
+            [
+                "body $.items[*].sku: expected \"B\", got \"A\"",
+                "body $.total: expected 100 < 100",
+            ]
+        );
+        assert_eq!(
+            m.mismatches(&view(&[], &[], None)),
+            [
+                "body $.items[*].sku: request body is not JSON",
+                "body $.total: request body is not JSON",
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_matchers_match_everything() {
+        let m = RequestMatchers::default();
+        assert!(m.is_empty());
+        assert!(m.matches(&view(&[], &[], None)));
+    }
+
+    #[test]
+    fn pattern_cache_drops_least_recently_used() {
+        let mut cache = PatternCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get("a"), Some(1));
+        cache.insert("c", 3);
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.get("c"), Some(3));
+    }
+}
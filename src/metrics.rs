@@ -11,26 +11,29 @@ use futures::future::LocalBoxFuture;
 use lazy_static::lazy_static;
 #[cfg(feature = "metrics")]
 use prometheus::{
-    register_counter_vec, register_histogram_vec, CounterVec, HistogramVec, TextEncoder,
+    register_counter_vec, register_histogram_vec, CounterVec, Encoder, HistogramVec, TextEncoder,
 };
 #[cfg(feature = "metrics")]
 use std::task::{Context, Poll};
 #[cfg(feature = "metrics")]
 use std::time::Instant;
 
+#[cfg(feature = "metrics")]
+use crate::matchers::protocol_name;
+
 // Define Prometheus metrics only when the 'metrics' feature is enabled
 #[cfg(feature = "metrics")]
 lazy_static! {
     pub static ref HTTP_REQUESTS_TOTAL: CounterVec = register_counter_vec!(
         "http_requests_total",
         "Number of HTTP requests made.",
-        &["method", "endpoint"]
+        &["method", "endpoint", "protocol"]
     )
     .unwrap();
     pub static ref HTTP_REQUESTS_DURATION_SECONDS: HistogramVec = register_histogram_vec!(
         "http_request_duration_seconds",
         "HTTP request latencies in seconds.",
-        &["method", "endpoint"]
+        &["method", "endpoint", "protocol"]
     )
     .unwrap();
     pub static ref HTTP_REQUESTS_ERRORS_TOTAL: CounterVec = register_counter_vec!(
@@ -115,6 +118,7 @@ where
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let start_time = Instant::now();
         let method = req.method().as_str().to_string();
+        let protocol = protocol_name(req.version());
         let path = req.path().to_string();
 
         let fut = self.service.call(req);
@@ -134,10 +138,10 @@ where
             };
 
             HTTP_REQUESTS_TOTAL
-                .with_label_values(&[&method, endpoint])
+                .with_label_values(&[&method, endpoint, protocol])
                 .inc();
             HTTP_REQUESTS_DURATION_SECONDS
-                .with_label_values(&[&method, endpoint])
+                .with_label_values(&[&method, endpoint, protocol])
                 .observe(duration);
 
             let status_code = res.status().as_u16().to_string();
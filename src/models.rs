@@ -5,16 +5,869 @@
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
 /// Struct representing a mock API
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MockAPI {
     pub id: Option<Uuid>,
     pub api_name: String,
+    #[serde(default)]
     pub response: String, // Can contain Handlebars placeholders
+    #[serde(default = "default_status")]
     pub status: u16,
-    pub delay: u64,               // Delay in milliseconds
-    pub method: String,           // HTTP method (e.g., GET, POST)
+    #[serde(default)]
+    pub delay: DelaySpec, // Delay in milliseconds, or a latency distribution
+    pub method: MethodSpec, // HTTP method (e.g., GET, POST), ANY, or a list
     pub timestamp: DateTime<Utc>, // Timestamp field
-}
\ No newline at end of file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matchers: Option<RequestMatchers>, // Request predicates selecting this variant
+    #[serde(default)]
+    pub priority: i32, // Lower values are evaluated first
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>, // Response headers, values can contain Handlebars placeholders
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub responses: Vec<MockResponse>, // Sequenced responses, replace response/status/delay when set
+    #[serde(default)]
+    pub sequence_mode: SequenceMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scenario: Option<String>, // Scenario this mock takes part in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub required_state: Option<String>, // Scenario state in which this mock matches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_state: Option<String>, // Scenario state to move to once this mock is served
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fault: Option<FaultSpec>, // Network failure to simulate instead of a clean response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub throttle: Option<ThrottleSpec>, // Stream the body slowly instead of all at once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sse: Option<SseSpec>, // Serve a text/event-stream instead of the response body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub websocket: Option<WebSocketSpec>, // Accept WebSocket upgrades on this api_name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub graphql: Option<GraphQLSpec>, // Treat requests as GraphQL operations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grpc: Option<GrpcSpec>, // Serve api_name `package.Service/Method` on the gRPC listener
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub callbacks: Vec<CallbackSpec>, // Outbound requests fired after the response is sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitSpec>, // Reject requests beyond a rate with a 429
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cors: Option<CorsConfig>, // Overrides the global CORS policy for this mock
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+/// One step of a sequenced mock
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MockResponse {
+    #[serde(default)]
+    pub response: String, // Can contain Handlebars placeholders
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>, // Merged over the mock-level headers
+    #[serde(default)]
+    pub delay: DelaySpec, // Delay in milliseconds, or a latency distribution
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fault: Option<FaultSpec>, // Replaces the mock-level fault for this step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub throttle: Option<ThrottleSpec>, // Replaces the mock-level throttle for this step
+}
+
+/// Server-Sent Events stream served by a mock
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SseSpec {
+    #[serde(default)]
+    pub events: Vec<SseEvent>,
+    #[serde(default)]
+    pub mode: SseMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<u64>, // Milliseconds between keep-alive comments in keep_open mode
+}
+
+/// One event of an SSE stream
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SseEvent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>, // Event name
+    #[serde(default)]
+    pub data: String, // Can contain Handlebars placeholders
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<u64>, // Reconnection time hint for the client, in milliseconds
+    #[serde(default)]
+    pub delay: u64, // Milliseconds to wait before sending this event
+}
+
+/// What an SSE stream does after its last event
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SseMode {
+    #[default]
+    Once, // Close the stream
+    Repeat,   // Start again from the first event
+    KeepOpen, // Keep the connection open, sending keep-alive comments
+}
+
+/// GraphQL mode of a mock: the response is the `data` of a GraphQL envelope
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GraphQLSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<String>, // Operation name this mock answers; None answers any operation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<Value>, // GraphQL errors, string values can contain Handlebars placeholders
+}
+
+/// gRPC mode of a mock: the response is JSON encoded as the method's output message
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GrpcSpec {
+    #[serde(default)]
+    pub code: u32, // gRPC status code sent in the trailers, 0 (OK) by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>, // grpc-message sent with a non-OK code
+}
+
+/// Method exposed by an uploaded protobuf descriptor
+#[derive(Debug, Clone, Serialize)]
+pub struct GrpcMethodInfo {
+    pub name: String, // `package.Service/Method`, the api_name of its mocks
+    pub input: String,
+    pub output: String,
+    pub client_streaming: bool,
+    pub server_streaming: bool,
+}
+
+/// Scripted WebSocket behaviour of a mock
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WebSocketSpec {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_connect: Vec<WsMessage>, // Sent in order once the connection is established
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replies: Vec<WsReply>, // The first reply matching an incoming message is sent
+}
+
+/// A text message sent by a WebSocket mock
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WsMessage {
+    #[serde(default)]
+    pub message: String, // Can contain Handlebars placeholders
+    #[serde(default)]
+    pub delay: u64, // Milliseconds to wait before sending this message
+}
+
+/// Messages sent back when an incoming message matches
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WsReply {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matches: Option<String>, // Regular expression on the incoming text
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub body: Vec<JsonPathMatcher>, // Predicates on the incoming message parsed as JSON
+    #[serde(default)]
+    pub messages: Vec<WsMessage>,
+}
+
+/// Simulated rate limit of a mock
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitSpec {
+    #[serde(default)]
+    pub algorithm: RateLimitAlgorithm,
+    pub limit: u32,  // Requests per window, or the bucket capacity
+    pub window: u64, // Window length in ms, or the time to refill an empty bucket
+    #[serde(default)]
+    pub key: RateLimitKey,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>, // Header whose value keys the limit when key is `header`
+    #[serde(default = "default_rate_limited_status")]
+    pub status: u16,
+    #[serde(default = "default_rate_limited_response")]
+    pub response: String, // Body of rejected requests
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>, // Extra headers of rejected requests
+}
+
+fn default_rate_limited_status() -> u16 {
+    429
+}
+
+fn default_rate_limited_response() -> String {
+    "\"Rate limit exceeded\"".to_string()
+}
+
+/// How requests are counted against a rate limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitAlgorithm {
+    #[default]
+    TokenBucket, // Tokens refill continuously; bursts up to `limit`
+    FixedWindow, // At most `limit` requests per window
+}
+
+/// What a rate limit is counted per
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    #[default]
+    Global, // One limit shared by every client
+    Ip,     // One limit per client IP
+    Header, // One limit per value of `header`, e.g. an API key
+}
+
+/// Outbound request (webhook) fired after a mock has been served
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallbackSpec {
+    pub url: String, // Can contain Handlebars placeholders
+    #[serde(default = "default_callback_method")]
+    pub method: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>, // Values can contain Handlebars placeholders
+    #[serde(default)]
+    pub body: String, // Can contain Handlebars placeholders
+    #[serde(default)]
+    pub delay: DelaySpec, // Wait before sending, after the response
+}
+
+fn default_callback_method() -> String {
+    "POST".to_string()
+}
+
+/// Outcome of a fired callback, as listed by the admin API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallbackRecord {
+    pub id: Uuid,
+    pub mock_id: Uuid,
+    pub timestamp: DateTime<Utc>, // When the callback was sent
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+    pub status: Option<u16>, // None when no response was received
+    pub response_body: Option<String>,
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// Filters for listing the callback log
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CallbackFilter {
+    pub mock_id: Option<Uuid>,
+    pub failed: Option<bool>, // Callbacks that got no 2xx response
+}
+
+/// A connected WebSocket client, as listed by the admin API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsSessionInfo {
+    pub id: Uuid,
+    pub mock_id: Uuid,
+    pub api_name: String,
+    pub connected_at: DateTime<Utc>,
+    pub messages_received: usize,
+}
+
+/// Request body for pushing a message to a connected WebSocket client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsPush {
+    pub message: String,
+}
+
+/// Bandwidth limit for streaming a response body
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThrottleSpec {
+    pub bytes_per_second: u64,
+    #[serde(default)]
+    pub first_byte: u64, // Milliseconds between the headers and the first body byte
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<usize>, // Bytes per chunk, defaults to a tenth of the rate
+    #[serde(default)]
+    pub chunked: bool, // Use chunked transfer encoding instead of Content-Length
+}
+
+/// Response delay: a fixed number of milliseconds or a latency distribution
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DelaySpec {
+    Fixed(u64),                      // 250
+    Distribution(DelayDistribution), // {"type": "uniform", "min": 50, "max": 200}
+}
+
+/// Latency distributions, all values in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DelayDistribution {
+    Fixed {
+        ms: u64,
+    },
+    Uniform {
+        min: u64,
+        max: u64,
+    },
+    Normal {
+        median: u64,
+        p99: u64,
+    },
+    Lognormal {
+        median: u64,
+        p99: u64,
+    },
+    Spike {
+        base: u64,
+        spike: u64,
+        probability: f64,
+    }, // `spike` with the given probability, else `base`
+}
+
+/// Request body for the global delay multiplier
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DelayMultiplier {
+    pub multiplier: f64,
+}
+
+/// Network failure injected in place of a clean response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FaultSpec {
+    #[serde(rename = "type")]
+    pub kind: FaultKind,
+    #[serde(default = "default_probability")]
+    pub probability: f64, // Chance (0 to 1) that a request gets the fault
+}
+
+fn default_probability() -> f64 {
+    1.0
+}
+
+/// Kinds of network failure a mock can simulate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultKind {
+    ConnectionReset, // Reset the connection (RST) instead of responding
+    EmptyReply,      // Close the connection without sending anything
+    TruncatedBody,   // Send half of the announced body, then close the connection
+    MalformedBody,   // Replace the second half of the body with random bytes
+    NeverComplete,   // Send the headers and part of the body, then stall forever
+}
+
+/// What a sequenced mock serves once its call counter passes the last response
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SequenceMode {
+    OnceThenNext, // Each response is served once; afterwards the mock no longer matches
+    Cycle,        // Start again from the first response
+    #[default]
+    StickOnLast, // Keep serving the last response
+}
+
+impl SequenceMode {
+    /// Index of the response to serve for the given call number, if any
+    pub fn step(self, call: usize, len: usize) -> Option<usize> {
+        if len == 0 {
+            return None;
+        }
+        match self {
+            SequenceMode::OnceThenNext => (call < len).then_some(call),
+            SequenceMode::Cycle => Some(call % len),
+            SequenceMode::StickOnLast => Some(call.min(len - 1)),
+        }
+    }
+}
+
+/// Request body for forcing a scenario into a state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScenarioStateUpdate {
+    pub state: String,
+}
+
+/// Upstreams that requests without a matching mock are forwarded to
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfig {
+    #[serde(default)]
+    pub upstream: Option<String>, // Global fallback base URL
+    #[serde(default)]
+    pub prefixes: BTreeMap<String, String>, // api_name prefix -> base URL, longest prefix wins
+}
+
+/// Which proxied requests are turned into mocks while recording
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordingConfig {
+    #[serde(default)]
+    pub include: Vec<String>, // api_name regexes to record; empty records everything
+    #[serde(default)]
+    pub exclude: Vec<String>, // api_name regexes never recorded
+    #[serde(default)]
+    pub repeat: RecordRepeat,
+}
+
+/// What recording does when a request already has a recorded mock
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordRepeat {
+    #[default]
+    KeepFirst, // Keep the first recorded response and ignore repeats
+    Sequence, // Append each repeat as the next sequenced response
+}
+
+/// Current recording state, as returned by the admin endpoints
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingStatus {
+    pub recording: bool,
+    pub config: Option<RecordingConfig>,
+}
+
+/// Retention limits of the request journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JournalConfig {
+    #[serde(default = "default_journal_entries")]
+    pub max_entries: usize, // Oldest requests are dropped beyond this; 0 disables the journal
+    #[serde(default = "default_journal_body_bytes")]
+    pub max_body_bytes: usize, // Longer request bodies are truncated
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        JournalConfig {
+            max_entries: default_journal_entries(),
+            max_body_bytes: default_journal_body_bytes(),
+        }
+    }
+}
+
+fn default_journal_entries() -> usize {
+    1000
+}
+
+fn default_journal_body_bytes() -> usize {
+    8192
+}
+
+/// A request received on a mock path
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub method: String,
+    #[serde(default)]
+    pub protocol: String, // HTTP version the request arrived with, e.g. `HTTP/2`
+    pub path: String,                      // Requested api_name
+    pub headers: BTreeMap<String, String>, // Header names are lower-cased
+    pub query: BTreeMap<String, String>,
+    pub body: String, // Lossy UTF-8, truncated to max_body_bytes
+    #[serde(default)]
+    pub body_truncated: bool,
+    pub mock_id: Option<Uuid>, // Mock that served the request, None if no mock matched
+    pub status: u16,
+}
+
+/// Filters for listing the request journal; all given filters must match
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct JournalFilter {
+    pub method: Option<String>,
+    pub protocol: Option<String>,
+    pub path: Option<String>, // Regex on the requested api_name
+    pub mock_id: Option<Uuid>,
+    pub matched: Option<bool>, // Whether some mock served the request
+    pub status: Option<u16>,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: Option<usize>, // Only the most recent entries
+}
+
+/// Request pattern checked against the journal, with the number of calls expected
+#[derive(Debug, Clone, Deserialize)]
+pub struct VerifyRequest {
+    #[serde(default = "default_verify_method")]
+    pub method: String, // "ANY" matches every method
+    pub path: String, // api_name, literal or with `{param}` placeholders
+    #[serde(default)]
+    pub matchers: Option<RequestMatchers>,
+    #[serde(default)]
+    pub count: CountExpectation,
+}
+
+fn default_verify_method() -> String {
+    "ANY".to_string()
+}
+
+/// Expected number of matching requests; with no bound set, at least one is expected
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CountExpectation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exactly: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at_least: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at_most: Option<usize>,
+}
+
+/// Outcome of a verification
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyResult {
+    pub passed: bool,
+    pub count: usize, // Journaled requests matching the pattern
+    pub expected: CountExpectation,
+    pub matched: Vec<JournalEntry>, // Requests matching the pattern, when the check failed
+    pub mismatched: Vec<RequestMismatch>, // Other journaled requests, when the check failed
+}
+
+/// A journaled request that does not match a pattern, with the reasons why
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestMismatch {
+    pub request: JournalEntry,
+    pub reasons: Vec<String>,
+}
+
+/// Whether unmatched requests get near-miss diagnostics, and how many mocks they list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NearMissConfig {
+    #[serde(default)]
+    pub enabled: bool, // Return and log diagnostics for unmatched requests
+    #[serde(default = "default_near_miss_candidates")]
+    pub max_candidates: usize,
+}
+
+impl Default for NearMissConfig {
+    fn default() -> Self {
+        NearMissConfig {
+            enabled: false,
+            max_candidates: default_near_miss_candidates(),
+        }
+    }
+}
+
+fn default_near_miss_candidates() -> usize {
+    3
+}
+
+/// Cross-origin (CORS) policy, applied to every route or overridden per mock
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorsConfig {
+    #[serde(default = "default_cors_enabled")]
+    pub enabled: bool, // Answer preflights and add `Access-Control-*` headers
+    #[serde(default = "default_cors_origins")]
+    pub allowed_origins: Vec<String>, // `*` allows any origin
+    #[serde(default)]
+    pub allowed_methods: Vec<String>, // Empty allows the requested method
+    #[serde(default)]
+    pub allowed_headers: Vec<String>, // Empty allows the requested headers
+    #[serde(default)]
+    pub exposed_headers: Vec<String>, // Response headers scripts may read
+    #[serde(default)]
+    pub allow_credentials: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>, // Seconds browsers may cache a preflight
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            enabled: false,
+            allowed_origins: default_cors_origins(),
+            allowed_methods: Vec::new(),
+            allowed_headers: Vec::new(),
+            exposed_headers: Vec::new(),
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+// A policy that is given is enabled unless it says otherwise
+fn default_cors_enabled() -> bool {
+    true
+}
+
+fn default_cors_origins() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+/// HTTPS listener settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub port: u16,
+    #[serde(default)]
+    pub cert_path: Option<String>, // PEM certificate chain; generated when unset
+    #[serde(default)]
+    pub key_path: Option<String>, // PEM private key of the supplied certificate
+    #[serde(default = "default_tls_hostnames")]
+    pub hostnames: Vec<String>, // Names and IPs of the generated certificate
+    #[serde(default)]
+    pub client_auth: ClientAuth,
+    #[serde(default)]
+    pub client_ca_path: Option<String>, // CA bundle trusted for client certificates
+}
+
+fn default_tls_hostnames() -> Vec<String> {
+    ["localhost", "127.0.0.1", "::1"]
+        .iter()
+        .map(|h| h.to_string())
+        .collect()
+}
+
+impl TlsConfig {
+    pub fn new(port: u16) -> Self {
+        TlsConfig {
+            port,
+            cert_path: None,
+            key_path: None,
+            hostnames: default_tls_hostnames(),
+            client_auth: ClientAuth::None,
+            client_ca_path: None,
+        }
+    }
+}
+
+/// Startup configuration: defaults, overridden by the configuration file, then by
+/// environment variables and command-line flags
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub sync: SyncSettings,
+    pub metrics: MetricsSettings,
+    pub proxy: ProxyConfig,
+    pub journal: JournalConfig,
+    pub near_miss: NearMissConfig,
+    pub cors: CorsConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>, // HTTPS is off when unset
+}
+
+/// HTTP listener settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    pub host: String,
+    pub port: u16,
+    pub workers: usize, // 0 = one per CPU
+    pub max_connections: usize,
+    pub backlog: u32,
+    pub grpc_enabled: bool, // Serve gRPC mocks on their own listener
+    pub grpc_port: u16,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            host: "0.0.0.0".to_string(),
+            port: 8080,
+            workers: 0,
+            max_connections: 20_000,
+            backlog: 1024,
+            grpc_enabled: true,
+            grpc_port: 50051,
+        }
+    }
+}
+
+/// Synchronization of mocks between pods
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncSettings {
+    pub token: String, // Sent as X-Internal-Token; must be the same on every pod
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_port: Option<u16>, // Port peers listen on; the server port when unset
+    pub interval_secs: u64,
+    pub namespace: String, // Kubernetes namespace the peer pods run in
+    pub app_label: String, // Value of the `app` label of the peer pods
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pod_ip: Option<String>, // This pod's IP, excluded from the peers
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        SyncSettings {
+            token: "S8d6xG1dA3fN7K9mA2jH4R6kB8vL0T5w".to_string(),
+            peer_port: None,
+            interval_secs: 60,
+            namespace: "default".to_string(),
+            app_label: "omock".to_string(),
+            pod_ip: None,
+        }
+    }
+}
+
+/// Prometheus metrics listener
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsSettings {
+    pub enabled: bool,
+    pub port: u16,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        MetricsSettings {
+            enabled: false,
+            port: 9090,
+        }
+    }
+}
+
+/// Whether HTTPS clients must present a certificate (mTLS)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAuth {
+    #[default]
+    None,
+    Optional, // Verify a certificate when one is presented
+    Required, // Refuse connections without a valid certificate
+}
+
+/// Request for a client certificate signed by the generated CA
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientCertRequest {
+    pub common_name: String,
+}
+
+/// A certificate and its private key, PEM encoded
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedCertificate {
+    pub cert: String,
+    pub key: String,
+}
+
+/// A mock that came close to serving a request, with the reasons it did not
+#[derive(Debug, Clone, Serialize)]
+pub struct NearMiss {
+    pub mock_id: Uuid,
+    pub api_name: String,
+    pub methods: Vec<String>,
+    pub path_distance: usize, // Edit distance from the requested path, 0 when the path matches
+    pub reasons: Vec<String>,
+}
+
+/// Response body for an unmatched request when diagnostics are enabled
+#[derive(Debug, Clone, Serialize)]
+pub struct UnmatchedReport {
+    pub message: String,
+    pub near_misses: Vec<NearMiss>,
+}
+
+/// A journaled request no mock served, with its closest mocks
+#[derive(Debug, Clone, Serialize)]
+pub struct UnmatchedRequest {
+    pub request: JournalEntry,
+    pub near_misses: Vec<NearMiss>,
+}
+
+/// HTTP method(s) a mock responds to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MethodSpec {
+    One(String),       // "GET" or "ANY"
+    Many(Vec<String>), // ["GET", "POST"]
+}
+
+impl Default for MethodSpec {
+    fn default() -> Self {
+        MethodSpec::One("GET".to_string())
+    }
+}
+
+impl MethodSpec {
+    /// Upper-cased method names this mock is indexed under
+    pub fn methods(&self) -> Vec<String> {
+        let mut methods: Vec<String> = match self {
+            MethodSpec::One(method) => vec![method.to_ascii_uppercase()],
+            MethodSpec::Many(list) => list.iter().map(|m| m.to_ascii_uppercase()).collect(),
+        };
+        methods.sort();
+        methods.dedup();
+        methods
+    }
+}
+
+/// Request predicates used to choose between mocks on the same route
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RequestMatchers {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<ValueMatcher>, // HTTP version, e.g. `HTTP/2`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, ValueMatcher>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub query: BTreeMap<String, ValueMatcher>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub body: Vec<JsonPathMatcher>,
+}
+
+/// Predicate on a single header or query parameter value
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ValueMatcher {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matches: Option<String>, // Regular expression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub present: Option<bool>, // false = must be absent
+}
+
+/// Predicate on the values selected by a JSONPath expression in the request body
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct JsonPathMatcher {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matches: Option<String>, // Regular expression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub present: Option<bool>, // false = path must select nothing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gt: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gte: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lt: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lte: Option<f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(mode: SequenceMode, len: usize) -> Vec<Option<usize>> {
+        (0..5).map(|call| mode.step(call, len)).collect()
+    }
+
+    #[test]
+    fn once_then_next_stops_after_the_last_response() {
+        assert_eq!(
+            steps(SequenceMode::OnceThenNext, 3),
+            [Some(0), Some(1), Some(2), None, None]
+        );
+    }
+
+    #[test]
+    fn cycle_starts_again_from_the_first_response() {
+        assert_eq!(
+            steps(SequenceMode::Cycle, 2),
+            [Some(0), Some(1), Some(0), Some(1), Some(0)]
+        );
+    }
+
+    #[test]
+    fn stick_on_last_repeats_the_last_response() {
+        assert_eq!(
+            steps(SequenceMode::StickOnLast, 2),
+            [Some(0), Some(1), Some(1), Some(1), Some(1)]
+        );
+    }
+
+    #[test]
+    fn no_responses_means_no_step() {
+        for mode in [
+            SequenceMode::OnceThenNext,
+            SequenceMode::Cycle,
+            SequenceMode::StickOnLast,
+        ] {
+            assert_eq!(mode.step(0, 0), None);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+// src/proxy.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+use log::info;
+use reqwest::{Client, Method, Url};
+
+use crate::models::ProxyConfig;
+
+// Connection-level headers that must not be forwarded by a proxy
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+];
+
+impl ProxyConfig {
+    /// Override the proxy configuration with `PROXY_UPSTREAM` and `PROXY_PREFIXES`
+    /// (comma-separated `prefix=url` pairs)
+    pub fn apply_env(&mut self) -> Result<(), String> {
+        if let Ok(url) = std::env::var("PROXY_UPSTREAM") {
+            self.upstream = Some(url).filter(|url| !url.is_empty());
+        }
+        if let Ok(prefixes) = std::env::var("PROXY_PREFIXES") {
+            for pair in prefixes.split(',').filter(|p| !p.trim().is_empty()) {
+                let (prefix, url) = pair
+                    .split_once('=')
+                    .ok_or_else(|| format!("expected prefix=url, got '{}'", pair))?;
+                self.prefixes
+                    .insert(prefix.trim().to_string(), url.trim().to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that every upstream is an absolute http(s) URL
+    pub fn validate(&self) -> Result<(), String> {
+        for url in self.upstream.iter().chain(self.prefixes.values()) {
+            let parsed =
+                Url::parse(url).map_err(|e| format!("invalid upstream '{}': {}", url, e))?;
+            if parsed.scheme() != "http" && parsed.scheme() != "https" {
+                return Err(format!("upstream '{}' must use http or https", url));
+            }
+        }
+        Ok(())
+    }
+
+    /// Upstream base URL for an api_name: the longest matching prefix, else the global upstream
+    pub fn upstream_for(&self, api_name: &str) -> Option<&str> {
+        self.prefixes
+            .iter()
+            .filter(|(prefix, _)| {
+                let prefix = prefix.trim_matches('/');
+                api_name == prefix
+                    || prefix.is_empty()
+                    || api_name
+                        .strip_prefix(prefix)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+            .max_by_key(|(prefix, _)| prefix.trim_matches('/').len())
+            .map(|(_, url)| url.as_str())
+            .or(self.upstream.as_deref())
+    }
+}
+
+/// Response received from an upstream, as forwarded back to the caller
+pub struct UpstreamResponse {
+    pub status: StatusCode,
+    pub headers: Vec<(String, Vec<u8>)>, // Without hop-by-hop headers and Content-Length
+    pub body: Vec<u8>,
+}
+
+impl UpstreamResponse {
+    /// Build the response returned to the caller
+    pub fn to_response(&self) -> HttpResponse {
+        let mut builder = HttpResponse::build(self.status);
+        for (name, value) in &self.headers {
+            builder.append_header((name.as_str(), value.as_slice()));
+        }
+        builder.body(self.body.clone())
+    }
+}
+
+/// `{upstream}/{api_name}` with the query string, whether or not the upstream ends with `/`
+fn upstream_url(upstream: &str, api_name: &str, query: &str) -> String {
+    let mut url = format!("{}/{}", upstream.trim_end_matches('/'), api_name);
+    if !query.is_empty() {
+        url.push('?');
+        url.push_str(query);
+    }
+    url
+}
+
+/// Forward a request to `{upstream}/{api_name}` with its method, headers and body.
+/// When `identity` is set the upstream is asked for an uncompressed body.
+pub async fn forward(
+    client: &Client,
+    upstream: &str,
+    api_name: &str,
+    req: &HttpRequest,
+    body: Vec<u8>,
+    identity: bool,
+) -> Result<UpstreamResponse, String> {
+    let url = upstream_url(upstream, api_name, req.query_string());
+
+    let method = Method::from_bytes(req.method().as_str().as_bytes()).map_err(|e| e.to_string())?;
+
+    let mut request = client.request(method, &url).body(body);
+    for (name, value) in req.headers() {
+        if HOP_BY_HOP_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        if identity && name.as_str() == "accept-encoding" {
+            continue;
+        }
+        request = request.header(name.as_str(), value.as_bytes());
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("error forwarding request to {}: {}", url, e))?;
+    info!(
+        "Proxied {} {} -> {}",
+        req.method(),
+        api_name,
+        response.status()
+    );
+
+    let status = StatusCode::from_u16(response.status().as_u16())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let headers = response
+        .headers()
+        .iter()
+        // Actix computes the length and framing of the body itself
+        .filter(|(name, _)| {
+            !HOP_BY_HOP_HEADERS.contains(&name.as_str()) && name.as_str() != "content-length"
+        })
+        .map(|(name, value)| (name.as_str().to_string(), value.as_bytes().to_vec()))
+        .collect();
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| format!("error reading upstream response from {}: {}", url, e))?;
+
+    Ok(UpstreamResponse {
+        status,
+        headers,
+        body: body.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(upstream: Option<&str>, prefixes: &[(&str, &str)]) -> ProxyConfig {
+        ProxyConfig {
+            upstream: upstream.map(str::to_string),
+            prefixes: prefixes
+                .iter()
+                .map(|(prefix, url)| (prefix.to_string(), url.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn longest_prefix_wins_over_the_default_upstream() {
+        let proxy = config(
+            Some("http://default"),
+            &[
+                ("payments", "http://payments"),
+                ("payments/refunds/", "http://refunds"),
+            ],
+        );
+        assert_eq!(proxy.upstream_for("payments"), Some("http://payments"));
+        assert_eq!(proxy.upstream_for("payments/42"), Some("http://payments"));
+        assert_eq!(
+            proxy.upstream_for("payments/refunds/7"),
+            Some("http://refunds")
+        );
+        assert_eq!(proxy.upstream_for("users/1"), Some("http://default"));
+    }
+
+    #[test]
+    fn prefixes_match_whole_segments() {
+        let proxy = config(None, &[("pay", "http://pay")]);
+        assert_eq!(proxy.upstream_for("pay/1"), Some("http://pay"));
+        assert_eq!(proxy.upstream_for("payments/1"), None);
+        assert_eq!(config(None, &[]).upstream_for("pay"), None);
+    }
+
+    #[test]
+    fn upstream_url_joins_with_a_single_slash() {
+        assert_eq!(
+            upstream_url("http://up:9000", "users/1", ""),
+            "http://up:9000/users/1"
+        );
+        assert_eq!(
+            upstream_url("http://up:9000/api/", "users/1", "page=2&sort=name"),
+            "http://up:9000/api/users/1?page=2&sort=name"
+        );
+    }
+
+    #[test]
+    fn upstreams_must_be_http_urls() {
+        assert!(config(Some("http://up"), &[("a", "https://a")])
+            .validate()
+            .is_ok());
+        assert!(config(Some("ftp://up"), &[]).validate().is_err());
+        assert!(config(None, &[("a", "not a url")]).validate().is_err());
+    }
+}
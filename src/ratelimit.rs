@@ -0,0 +1,313 @@
+// src/ratelimit.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+use log::debug;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::models::{RateLimitAlgorithm, RateLimitKey, RateLimitSpec};
+use crate::state::AppState;
+
+/// Limiter state of one (mock, client key) pair
+#[derive(Debug, Clone)]
+pub struct RateBucket {
+    level: f64,       // Tokens left (token bucket) or requests counted (fixed window)
+    since: Instant,   // Last refill (token bucket) or start of the window (fixed window)
+    window: Duration, // Once idle this long, the bucket is back to a fresh one
+}
+
+impl RateBucket {
+    /// A bucket with the whole limit available
+    pub fn new(spec: &RateLimitSpec, now: Instant) -> Self {
+        RateBucket {
+            level: match spec.algorithm {
+                RateLimitAlgorithm::TokenBucket => f64::from(spec.limit),
+                RateLimitAlgorithm::FixedWindow => 0.0,
+            },
+            since: now,
+            window: Duration::from_millis(spec.window),
+        }
+    }
+}
+
+// How often idle buckets are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Outcome of counting a request against a rate limit
+#[derive(Debug, Clone)]
+pub struct RateDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset: Duration,       // Until the limit is fully available again
+    pub retry_after: Duration, // Until the next request would be allowed
+}
+
+impl RateDecision {
+    /// `X-RateLimit-*` headers, plus `Retry-After` for rejected requests
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![
+            ("X-RateLimit-Limit", self.limit.to_string()),
+            ("X-RateLimit-Remaining", self.remaining.to_string()),
+            ("X-RateLimit-Reset", whole_seconds(self.reset).to_string()),
+        ];
+        if !self.allowed {
+            headers.push((
+                "Retry-After",
+                whole_seconds(self.retry_after).max(1).to_string(),
+            ));
+        }
+        headers
+    }
+}
+
+impl RateLimitSpec {
+    /// Check the limit, window, key and rejection status
+    pub fn validate(&self) -> Result<(), String> {
+        if self.limit == 0 {
+            return Err("rate_limit.limit must be greater than 0".to_string());
+        }
+        if self.window == 0 {
+            return Err("rate_limit.window must be greater than 0".to_string());
+        }
+        match (self.key, &self.header) {
+            (RateLimitKey::Header, None) => {
+                return Err("rate_limit.header is required when key is header".to_string())
+            }
+            (RateLimitKey::Global | RateLimitKey::Ip, Some(_)) => {
+                return Err("rate_limit.header is only used when key is header".to_string())
+            }
+            _ => {}
+        }
+        if !(100..=599).contains(&self.status) {
+            return Err("rate_limit.status must be an HTTP status code".to_string());
+        }
+        for (name, value) in &self.headers {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("rate_limit header name '{}' is invalid", name))?;
+            HeaderValue::from_str(value)
+                .map_err(|_| format!("rate_limit header {} has an invalid value", name))?;
+        }
+        Ok(())
+    }
+
+    /// The client a request is counted for
+    pub fn client_key(&self, req: &HttpRequest) -> String {
+        match self.key {
+            RateLimitKey::Global => String::new(),
+            RateLimitKey::Ip => {
+                let info = req.connection_info();
+                let addr = info.realip_remote_addr().unwrap_or_default();
+                // Drop the port of `ip:port` and `[ipv6]:port` addresses
+                match addr.rsplit_once(':') {
+                    Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host
+                        .trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .to_string(),
+                    _ => addr.to_string(),
+                }
+            }
+            RateLimitKey::Header => self
+                .header
+                .as_ref()
+                .and_then(|name| req.headers().get(name.as_str()))
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+
+    /// The client a gRPC call is counted for, from the peer address and the call's
+    /// metadata (with lower-cased names)
+    pub fn grpc_client_key(&self, peer: IpAddr, metadata: &HashMap<String, String>) -> String {
+        match self.key {
+            RateLimitKey::Global => String::new(),
+            RateLimitKey::Ip => peer.to_string(),
+            RateLimitKey::Header => self
+                .header
+                .as_ref()
+                .and_then(|name| metadata.get(&name.to_ascii_lowercase()))
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Count a request against the limit
+    fn count(&self, bucket: &mut RateBucket, now: Instant) -> RateDecision {
+        let limit = f64::from(self.limit);
+        let window = Duration::from_millis(self.window);
+        match self.algorithm {
+            RateLimitAlgorithm::TokenBucket => {
+                let per_second = limit / window.as_secs_f64();
+                let elapsed = now.duration_since(bucket.since).as_secs_f64();
+                bucket.level = (bucket.level + elapsed * per_second).min(limit);
+                bucket.since = now;
+
+                let allowed = bucket.level >= 1.0;
+                if allowed {
+                    bucket.level -= 1.0;
+                }
+                RateDecision {
+                    allowed,
+                    limit: self.limit,
+                    remaining: bucket.level.floor() as u32,
+                    reset: Duration::from_secs_f64((limit - bucket.level) / per_second),
+                    retry_after: Duration::from_secs_f64(
+                        (1.0 - bucket.level).max(0.0) / per_second,
+                    ),
+                }
+            }
+            RateLimitAlgorithm::FixedWindow => {
+                if now.duration_since(bucket.since) >= window {
+                    bucket.since = now;
+                    bucket.level = 0.0;
+                }
+                let allowed = bucket.level < limit;
+                if allowed {
+                    bucket.level += 1.0;
+                }
+                let reset = window.saturating_sub(now.duration_since(bucket.since));
+                RateDecision {
+                    allowed,
+                    limit: self.limit,
+                    remaining: (limit - bucket.level) as u32,
+                    reset,
+                    retry_after: if allowed { Duration::ZERO } else { reset },
+                }
+            }
+        }
+    }
+}
+
+impl AppState {
+    /// Count a request to a mock against its rate limit
+    pub fn check_rate_limit(
+        &self,
+        mock_id: Uuid,
+        spec: &RateLimitSpec,
+        key: String,
+    ) -> RateDecision {
+        let now = Instant::now();
+        let mut bucket = self
+            .rate_limits
+            .entry((mock_id, key))
+            .or_insert_with(|| RateBucket::new(spec, now));
+        spec.count(&mut bucket, now)
+    }
+
+    /// Drop the buckets idle for a whole window: a new bucket would start in the same
+    /// state, so every client key seen does not stay in memory
+    pub fn prune_rate_limits(&self) -> usize {
+        let now = Instant::now();
+        let before = self.rate_limits.len();
+        self.rate_limits
+            .retain(|_, bucket| now.duration_since(bucket.since) < bucket.window);
+        before.saturating_sub(self.rate_limits.len())
+    }
+
+    /// Drop the limiter state of a mock, for every client
+    pub fn reset_rate_limits(&self, mock_id: Uuid) {
+        self.rate_limits.retain(|(id, _), _| *id != mock_id);
+    }
+}
+
+/// The configured rejection of a rate limited request, with its rate limit headers
+pub fn limited_response(spec: &RateLimitSpec, decision: &RateDecision) -> HttpResponse {
+    let mut response = HttpResponse::build(
+        StatusCode::from_u16(spec.status).unwrap_or(StatusCode::TOO_MANY_REQUESTS),
+    );
+    if !spec
+        .headers
+        .keys()
+        .any(|name| name.eq_ignore_ascii_case("Content-Type"))
+    {
+        response.append_header(("Content-Type", "application/json"));
+    }
+    for header in decision.headers() {
+        response.append_header(header);
+    }
+    for (name, value) in &spec.headers {
+        response.append_header((name.as_str(), value.as_str()));
+    }
+    response.body(spec.response.clone())
+}
+
+/// Periodically drop the idle rate limit buckets
+pub async fn prune_idle_buckets(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let pruned = state.prune_rate_limits();
+        if pruned > 0 {
+            debug!("Dropped {} idle rate limit buckets", pruned);
+        }
+    }
+}
+
+fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(algorithm: RateLimitAlgorithm, limit: u32, window: u64) -> RateLimitSpec {
+        serde_json::from_value(serde_json::json!({ "limit": limit, "window": window }))
+            .map(|spec: RateLimitSpec| RateLimitSpec { algorithm, ..spec })
+            .unwrap()
+    }
+
+    #[test]
+    fn fixed_window_resets_after_the_window() {
+        let spec = spec(RateLimitAlgorithm::FixedWindow, 2, 1000);
+        let start = Instant::now();
+        let mut bucket = RateBucket::new(&spec, start);
+
+        let first = spec.count(&mut bucket, start);
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 1);
+        assert!(spec.count(&mut bucket, start).allowed);
+
+        let later = start + Duration::from_millis(400);
+        let rejected = spec.count(&mut bucket, later);
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.remaining, 0);
+        assert_eq!(rejected.retry_after, Duration::from_millis(600));
+
+        let next_window = start + Duration::from_millis(1000);
+        let allowed = spec.count(&mut bucket, next_window);
+        assert!(allowed.allowed);
+        assert_eq!(allowed.remaining, 1);
+    }
+
+    #[test]
+    fn token_bucket_refills_over_time() {
+        let spec = spec(RateLimitAlgorithm::TokenBucket, 2, 1000);
+        let start = Instant::now();
+        let mut bucket = RateBucket::new(&spec, start);
+
+        assert!(spec.count(&mut bucket, start).allowed);
+        assert!(spec.count(&mut bucket, start).allowed);
+        let rejected = spec.count(&mut bucket, start);
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.retry_after, Duration::from_millis(500));
+
+        // Half a window refills one token, never more than the capacity
+        let refilled = spec.count(&mut bucket, start + Duration::from_millis(500));
+        assert!(refilled.allowed);
+        assert_eq!(refilled.remaining, 0);
+
+        let idle = spec.count(&mut bucket, start + Duration::from_secs(10));
+        assert!(idle.allowed);
+        assert_eq!(idle.remaining, 1);
+    }
+}
@@ -0,0 +1,244 @@
+// src/recorder.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use chrono::Utc;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::matchers::compiled_regex;
+use crate::models::{
+    MethodSpec, MockAPI, MockResponse, RecordingConfig, RequestMatchers, ValueMatcher,
+};
+use crate::proxy::UpstreamResponse;
+use crate::router::PathPattern;
+
+// Upstream headers that describe the original exchange rather than the resource
+const UNRECORDED_HEADERS: [&str; 2] = ["date", "content-encoding"];
+
+impl RecordingConfig {
+    /// Check that every path filter is a valid regex
+    pub fn validate(&self) -> Result<(), String> {
+        for pattern in self.include.iter().chain(self.exclude.iter()) {
+            compiled_regex(pattern).map_err(|e| format!("filter '{}': {}", pattern, e))?;
+        }
+        Ok(())
+    }
+
+    /// Returns true when requests to this api_name should be recorded
+    pub fn captures(&self, api_name: &str) -> bool {
+        let matches = |pattern: &String| {
+            compiled_regex(pattern)
+                .map(|regex| regex.is_match(api_name))
+                .unwrap_or(false)
+        };
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Turn a proxied request/response pair into a mock. Query parameters become
+/// exact-match matchers so different queries are recorded as separate variants.
+pub fn recorded_mock(
+    method: &str,
+    api_name: &str,
+    query: &HashMap<String, String>,
+    upstream: &UpstreamResponse,
+) -> Result<MockAPI, String> {
+    let body = String::from_utf8(upstream.body.clone())
+        .map_err(|_| "response body is not UTF-8".to_string())?;
+
+    // Repeated headers such as Set-Cookie keep every value, one per line
+    let mut headers: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in &upstream.headers {
+        if UNRECORDED_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        let value = String::from_utf8(value.clone())
+            .map_err(|_| format!("header {} is not UTF-8", name))?;
+        let value = escape_template(&value);
+        headers
+            .entry(name.clone())
+            .and_modify(|values| {
+                values.push('\n');
+                values.push_str(&value);
+            })
+            .or_insert(value);
+    }
+
+    let query: BTreeMap<String, ValueMatcher> = query
+        .iter()
+        .map(|(name, value)| {
+            let matcher = ValueMatcher {
+                equals: Some(value.clone()),
+                ..Default::default()
+            };
+            (name.clone(), matcher)
+        })
+        .collect();
+    let matchers = (!query.is_empty()).then(|| RequestMatchers {
+        query,
+        ..Default::default()
+    });
+
+    Ok(MockAPI {
+        api_name: escape_path(api_name),
+        response: escape_template(&body),
+        status: upstream.status.as_u16(),
+        method: MethodSpec::One(method.to_ascii_uppercase()),
+        timestamp: Utc::now(),
+        matchers,
+        headers,
+        ..Default::default()
+    })
+}
+
+/// Append a recorded response to an existing mock as the next sequenced response
+pub fn append_step(existing: &MockAPI, recorded: &MockAPI) -> MockAPI {
+    let mut mock = existing.clone();
+    if mock.responses.is_empty() {
+        // The single response becomes the first step; its headers move with it
+        mock.responses.push(MockResponse {
+            response: mock.response.clone(),
+            status: mock.status,
+            headers: std::mem::take(&mut mock.headers),
+            delay: mock.delay.clone(),
+            ..Default::default()
+        });
+    }
+    mock.responses.push(MockResponse {
+        response: recorded.response.clone(),
+        status: recorded.status,
+        headers: recorded.headers.clone(),
+        delay: recorded.delay.clone(),
+        ..Default::default()
+    });
+    mock.timestamp = Utc::now();
+    mock
+}
+
+/// Escape Handlebars expressions so recorded text is served literally
+fn escape_template(text: &str) -> String {
+    text.replace("{{", "\\{{")
+}
+
+/// Escape braces so a recorded path is matched literally instead of as a `{param}` pattern
+fn escape_path(api_name: &str) -> String {
+    if !PathPattern::is_pattern(api_name) {
+        return api_name.to_string();
+    }
+    let mut escaped = String::with_capacity(api_name.len());
+    for c in api_name.chars() {
+        if matches!(c, '{' | '}' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::RouteEntry;
+    use actix_web::http::StatusCode;
+    use uuid::Uuid;
+
+    fn upstream(status: u16, headers: &[(&str, &str)], body: &str) -> UpstreamResponse {
+        UpstreamResponse {
+            status: StatusCode::from_u16(status).unwrap(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+                .collect(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn escaped_paths_match_the_recorded_request_path() {
+        for path in [r"files/{draft}", r"a}b/{{x}}/c\d", r"odd\{/x"] {
+            let mock =
+                recorded_mock("GET", path, &HashMap::new(), &upstream(200, &[], "")).unwrap();
+            assert!(PathPattern::is_pattern(&mock.api_name));
+            let entry = RouteEntry::new(Uuid::nil(), &mock);
+            let pattern = PathPattern::compile("GET", &mock.api_name, entry).unwrap();
+            assert_eq!(
+                pattern.captures(path),
+                Some(serde_json::Map::new()),
+                "{}",
+                path
+            );
+            assert!(pattern.captures("files/x").is_none());
+        }
+
+        // Paths without braces are kept as they are, backslashes included
+        let mock = recorded_mock(
+            "GET",
+            r"plain\path",
+            &HashMap::new(),
+            &upstream(200, &[], ""),
+        )
+        .unwrap();
+        assert_eq!(mock.api_name, r"plain\path");
+    }
+
+    #[test]
+    fn recorded_mock_keeps_repeated_headers_and_escapes_templates() {
+        let response = upstream(
+            201,
+            &[
+                ("set-cookie", "a=1"),
+                ("date", "Mon, 01 Jan 2024 00:00:00 GMT"),
+                ("set-cookie", "b=2"),
+                ("x-template", "{{not}}"),
+            ],
+            r#"{"name": "{{user}}"}"#,
+        );
+        let query = HashMap::from([("page".to_string(), "2".to_string())]);
+        let mock = recorded_mock("post", "users", &query, &response).unwrap();
+
+        assert_eq!(mock.status, 201);
+        assert_eq!(mock.method, MethodSpec::One("POST".to_string()));
+        assert_eq!(mock.response, r#"{"name": "\{{user}}"}"#);
+        assert_eq!(
+            mock.headers,
+            BTreeMap::from([
+                ("set-cookie".to_string(), "a=1\nb=2".to_string()),
+                ("x-template".to_string(), r"\{{not}}".to_string()),
+            ])
+        );
+        let page = &mock.matchers.unwrap().query["page"];
+        assert_eq!(page.equals.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn append_step_turns_the_single_response_into_a_sequence() {
+        let first = recorded_mock(
+            "GET",
+            "items",
+            &HashMap::new(),
+            &upstream(200, &[("x-a", "1")], "one"),
+        )
+        .unwrap();
+        let second =
+            recorded_mock("GET", "items", &HashMap::new(), &upstream(404, &[], "two")).unwrap();
+        let third = recorded_mock(
+            "GET",
+            "items",
+            &HashMap::new(),
+            &upstream(200, &[], "three"),
+        )
+        .unwrap();
+
+        let mock = append_step(&append_step(&first, &second), &third);
+        let steps: Vec<(&str, u16)> = mock
+            .responses
+            .iter()
+            .map(|step| (step.response.as_str(), step.status))
+            .collect();
+        assert_eq!(steps, [("one", 200), ("two", 404), ("three", 200)]);
+        assert_eq!(mock.responses[0].headers["x-a"], "1");
+        assert!(mock.headers.is_empty());
+    }
+}
@@ -0,0 +1,316 @@
+// src/router.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+use std::fmt;
+use uuid::Uuid;
+
+use crate::models::{MethodSpec, MockAPI, RequestMatchers};
+
+/// Method name that matches every request method
+pub const ANY_METHOD: &str = "ANY";
+
+/// Routing index key: (upper-cased HTTP method, api_name)
+pub type RouteKey = (String, String);
+
+/// Build the routing key for a method and api_name
+pub fn route_key(method: &str, api_name: &str) -> RouteKey {
+    (method.to_ascii_uppercase(), api_name.to_string())
+}
+
+/// One mock variant registered on a route
+#[derive(Debug, Clone)]
+pub struct RouteEntry {
+    pub mock_id: Uuid,
+    pub priority: i32,
+    pub matchers: Option<RequestMatchers>, // None for the default variant
+    pub scenario_state: Option<(String, String)>, // (scenario, required state)
+    pub operation: Option<String>,         // GraphQL operation name
+}
+
+impl RouteEntry {
+    pub fn new(mock_id: Uuid, mock: &MockAPI) -> Self {
+        RouteEntry {
+            mock_id,
+            priority: mock.priority,
+            matchers: mock.matchers.clone().filter(|m| !m.is_empty()),
+            scenario_state: mock.scenario.clone().zip(mock.required_state.clone()),
+            operation: mock.graphql.as_ref().and_then(|g| g.operation.clone()),
+        }
+    }
+
+    /// Returns true when both entries would be selected by exactly the same requests
+    pub fn same_variant(&self, other: &Self) -> bool {
+        self.matchers == other.matchers
+            && self.scenario_state == other.scenario_state
+            && self.operation == other.operation
+    }
+
+    /// Returns true for the unconditional default variant of a route
+    pub fn is_default(&self) -> bool {
+        self.matchers.is_none() && self.scenario_state.is_none() && self.operation.is_none()
+    }
+
+    /// Evaluation order within a route: conditional variants by priority, default last
+    pub fn rank_cmp(&self, other: &Self) -> Ordering {
+        self.is_default()
+            .cmp(&other.is_default())
+            .then(self.priority.cmp(&other.priority))
+    }
+}
+
+/// Error raised when a mock cannot be added to the routing index
+#[derive(Debug)]
+pub enum RouteError {
+    InvalidMethod(String),
+    InvalidPattern(String),
+    InvalidMatchers(String),
+    Conflict(Uuid),
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::InvalidMethod(e) => write!(f, "Invalid method: {}", e),
+            RouteError::InvalidPattern(e) => write!(f, "Invalid api_name pattern: {}", e),
+            RouteError::InvalidMatchers(e) => write!(f, "Invalid matchers: {}", e),
+            RouteError::Conflict(id) => {
+                write!(
+                    f,
+                    "A mock with the same method, api_name and matchers already exists: {}",
+                    id
+                )
+            }
+        }
+    }
+}
+
+/// Check that a method spec names at least one valid HTTP method (or ANY)
+pub fn validate_methods(spec: &MethodSpec) -> Result<Vec<String>, String> {
+    let methods = spec.methods();
+    if methods.is_empty() {
+        return Err("at least one method is required".to_string());
+    }
+    for method in &methods {
+        if method != ANY_METHOD && actix_web::http::Method::from_bytes(method.as_bytes()).is_err() {
+            return Err(format!("'{}' is not a valid HTTP method", method));
+        }
+    }
+    Ok(methods)
+}
+
+/// Compiled `api_name` pattern such as `users/{userId}/orders/{orderId}`
+#[derive(Debug, Clone)]
+pub struct PathPattern {
+    pub method: String,
+    pub entry: RouteEntry,
+    shape: String, // api_name with parameter names erased, used for duplicate detection
+    regex: Regex,
+    params: Vec<String>,
+    literal_segments: usize,
+    literal_chars: usize,
+}
+
+impl PathPattern {
+    /// Returns true when the api_name contains `{param}` placeholders
+    pub fn is_pattern(api_name: &str) -> bool {
+        api_name.contains('{') || api_name.contains('}')
+    }
+
+    /// Compile an api_name into a pattern. Both `{name}` and `{{name}}` are accepted;
+    /// `\{`, `\}` and `\\` stand for a literal brace or backslash.
+    pub fn compile(method: &str, api_name: &str, entry: RouteEntry) -> Result<Self, String> {
+        let mut expr = String::from("^");
+        let mut shape = String::new();
+        let mut params: Vec<String> = Vec::new();
+        let mut literal = String::new();
+        let mut literal_chars = 0;
+        let mut chars = api_name.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let double = chars.peek() == Some(&'{');
+                    if double {
+                        chars.next();
+                    }
+
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(ch) => name.push(ch),
+                            None => return Err(format!("unclosed '{{' in '{}'", api_name)),
+                        }
+                    }
+                    if double && chars.next() != Some('}') {
+                        return Err(format!("expected '}}}}' after '{}'", name));
+                    }
+
+                    let name = name.trim().to_string();
+                    if !is_valid_param_name(&name) {
+                        return Err(format!("invalid path parameter name '{}'", name));
+                    }
+                    if params.contains(&name) {
+                        return Err(format!("duplicate path parameter '{}'", name));
+                    }
+
+                    expr.push_str(&regex::escape(&literal));
+                    shape.push_str(&literal);
+                    literal_chars += literal.len();
+                    literal.clear();
+                    expr.push_str(&format!("(?P<{}>[^/]+)", name));
+                    shape.push_str("{}");
+                    params.push(name);
+                }
+                '\\' if matches!(chars.peek(), Some('{' | '}' | '\\')) => {
+                    literal.push(chars.next().unwrap());
+                }
+                '}' => return Err(format!("unexpected '}}' in '{}'", api_name)),
+                _ => literal.push(c),
+            }
+        }
+        expr.push_str(&regex::escape(&literal));
+        shape.push_str(&literal);
+        literal_chars += literal.len();
+        expr.push('$');
+
+        let regex = Regex::new(&expr).map_err(|e| e.to_string())?;
+
+        let literal_segments = api_name
+            .replace("\\\\", "")
+            .replace("\\{", "")
+            .split('/')
+            .filter(|segment| !segment.contains('{'))
+            .count();
+
+        Ok(PathPattern {
+            method: method.to_ascii_uppercase(),
+            entry,
+            shape,
+            regex,
+            params,
+            literal_segments,
+            literal_chars,
+        })
+    }
+
+    /// Returns true when both patterns would match exactly the same requests
+    pub fn same_route(&self, other: &Self) -> bool {
+        self.method == other.method
+            && self.shape == other.shape
+            && self.entry.same_variant(&other.entry)
+    }
+
+    /// Match a request path, returning the captured parameters on success
+    pub fn captures(&self, path: &str) -> Option<Map<String, Value>> {
+        let caps = self.regex.captures(path)?;
+        let mut params = Map::new();
+        for name in &self.params {
+            if let Some(value) = caps.name(name) {
+                params.insert(name.clone(), Value::String(value.as_str().to_string()));
+            }
+        }
+        Some(params)
+    }
+
+    /// Ordering used for pattern lookup: more specific patterns come first,
+    /// variants of the same pattern are kept together in rank order
+    pub fn specificity_cmp(&self, other: &Self) -> Ordering {
+        other
+            .literal_segments
+            .cmp(&self.literal_segments)
+            .then(other.literal_chars.cmp(&self.literal_chars))
+            .then(self.params.len().cmp(&other.params.len()))
+            .then(self.shape.cmp(&other.shape))
+            .then(self.entry.rank_cmp(&other.entry))
+    }
+}
+
+fn is_valid_param_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(api_name: &str) -> PathPattern {
+        let entry = RouteEntry::new(Uuid::nil(), &MockAPI::default());
+        PathPattern::compile("GET", api_name, entry).unwrap()
+    }
+
+    #[test]
+    fn compile_captures_parameters() {
+        let params = pattern("users/{id}/orders/{{orderId}}")
+            .captures("users/42/orders/7")
+            .unwrap();
+        assert_eq!(params["id"], "42");
+        assert_eq!(params["orderId"], "7");
+
+        let params = pattern("files/user-{name}.json")
+            .captures("files/user-ada.json")
+            .unwrap();
+        assert_eq!(params["name"], "ada");
+    }
+
+    #[test]
+    fn parameters_match_a_single_segment() {
+        let pattern = pattern("users/{id}");
+        assert!(pattern.captures("users/").is_none());
+        assert!(pattern.captures("users/1/2").is_none());
+        assert!(pattern.captures("accounts/1").is_none());
+    }
+
+    #[test]
+    fn escaped_braces_are_literal() {
+        let pattern = pattern(r"files/\{draft\}/\\");
+        assert!(pattern.captures(r"files/{draft}/\").is_some());
+        assert!(pattern.captures("files/x/\\").is_none());
+    }
+
+    #[test]
+    fn compile_rejects_invalid_patterns() {
+        let entry = RouteEntry::new(Uuid::nil(), &MockAPI::default());
+        for api_name in [
+            "users/{id",
+            "users/id}",
+            "users/{1id}",
+            "users/{}",
+            "{id}/{id}",
+            "users/{{id}",
+        ] {
+            assert!(
+                PathPattern::compile("GET", api_name, entry.clone()).is_err(),
+                "{}",
+                api_name
+            );
+        }
+    }
+
+    #[test]
+    fn specificity_prefers_literal_segments_then_characters() {
+        let mut patterns = [
+            pattern("{a}/{b}"),
+            pattern("users/{id}"),
+            pattern("users/{id}/orders"),
+            pattern("users/me-{id}"),
+        ];
+        patterns.sort_by(|a, b| a.specificity_cmp(b));
+        let order: Vec<&str> = patterns.iter().map(|p| p.shape.as_str()).collect();
+        assert_eq!(
+            order,
+            ["users/{}/orders", "users/me-{}", "users/{}", "{}/{}"]
+        );
+    }
+}
@@ -3,14 +3,32 @@
 // Author: Md Hasan Basri
 // Email: pothiq@gmail.com
 
-use crate::models::MockAPI;
-use crate::state::AppState;
+use crate::delays::MAX_DELAY_MULTIPLIER;
+use crate::graphql::{self, GraphQLRequest};
+use crate::grpc;
+use crate::matchers::{protocol_name, RequestView};
+use crate::models::{
+    CallbackFilter, ClientCertRequest, CorsConfig, DelayMultiplier, GrpcMethodInfo, JournalConfig,
+    JournalEntry, JournalFilter, MockAPI, NearMissConfig, ProxyConfig, RecordRepeat,
+    RecordingConfig, RecordingStatus, ScenarioStateUpdate, UnmatchedReport, VerifyRequest, WsPush,
+    WsSessionInfo,
+};
+use crate::router::RouteError;
+use crate::state::{
+    body_template_name, callback_template_sources, graphql_errors_template_name,
+    header_template_name, template_sources, AppState, SwapError, STARTED_STATE,
+};
 use crate::utils::get_other_pod_ips;
-use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
+use crate::{callbacks, diagnostics, faults, proxy, ratelimit, recorder, sse, throttle, websocket};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{
+    delete, get, post, put, web, HttpRequest, HttpResponse, HttpResponseBuilder, Responder,
+};
 use actix_web_codegen::route; // Import the route attribute macro from actix_web_codegen
 use log::{error, info};
 use reqwest::Client;
 use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::atomic::Ordering;
 use tokio::spawn; // <-- Add this line
 use uuid::Uuid;
@@ -22,45 +40,48 @@ pub async fn update_mock(
     data: web::Json<MockAPI>,
     state: web::Data<AppState>,
 ) -> impl Responder {
-    let mock_id = path.into_inner();
-    let updated_mock = data.into_inner();
+    match replace_mock(&state, path.into_inner(), data.into_inner()).await {
+        Ok(updated_mock) => HttpResponse::Ok().json(updated_mock),
+        Err(response) => response,
+    }
+}
+
+/// Validate and apply an update to an existing mock, then propagate it to the other pods
+pub async fn replace_mock(
+    state: &AppState,
+    mock_id: Uuid,
+    updated_mock: MockAPI,
+) -> Result<MockAPI, HttpResponse> {
+    if let Err(e) = validate_response(&updated_mock) {
+        return Err(HttpResponse::BadRequest().json(e));
+    }
+
+    // Reject updates that would collide with another mock
+    if let Err(e) = state.check_route(mock_id, &updated_mock) {
+        return Err(route_error_response(e));
+    }
 
     // Perform local mutation
     if let Some(mut mock_entry) = state.mocks.get_mut(&mock_id) {
-        // If api_name has changed, update the mapping
-        if mock_entry.api_name != updated_mock.api_name {
-            // Remove the old mapping
-            state.api_name_to_id.remove(&mock_entry.api_name);
-            // Insert the new mapping
-            state
-                .api_name_to_id
-                .insert(updated_mock.api_name.clone(), mock_id);
-        }
-
-        // Update the MockAPI fields
-        mock_entry.api_name = updated_mock.api_name.clone();
-        mock_entry.response = updated_mock.response.clone();
-        mock_entry.status = updated_mock.status;
-        mock_entry.delay = updated_mock.delay;
-        mock_entry.method = updated_mock.method.clone();
-        mock_entry.timestamp = updated_mock.timestamp; // Update timestamp
-
-        // Register the template
-        let template_name = mock_id.to_string();
-        let mut handlebars = state.handlebars.lock().unwrap();
-        match handlebars.register_template_string(&template_name, &updated_mock.response) {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!("Error compiling template: {}", e);
-                return HttpResponse::InternalServerError().json("Error compiling template");
-            }
+        // Re-index under the (possibly changed) method, api_name and matchers, and
+        // recompile the templates; the old version stays in place on failure
+        if let Err(e) = state.swap_mock(mock_id, &mock_entry, &updated_mock) {
+            return Err(swap_error_response(e));
         }
+        state.sequence_counters.remove(&mock_id);
+        state.reset_rate_limits(mock_id);
+
+        // Replace every field but the id
+        *mock_entry = MockAPI {
+            id: mock_entry.id,
+            ..updated_mock.clone()
+        };
     } else {
-        return HttpResponse::NotFound().json("Mock not found");
+        return Err(HttpResponse::NotFound().json("Mock not found"));
     }
 
     // Synchronize with other pods
-    let other_pod_ips = match get_other_pod_ips().await {
+    let other_pod_ips = match get_other_pod_ips(&state.config.sync).await {
         Ok(ips) => ips,
         Err(e) => {
             eprintln!("Failed to get other pod IPs: {}", e);
@@ -69,22 +90,28 @@ pub async fn update_mock(
     };
 
     let client = Client::new();
+    let peer_port = state.config.peer_port();
 
     for ip in other_pod_ips {
-        let url = format!("http://{}:8080/update-mock-internal/{}", ip, mock_id);
+        let url = format!(
+            "http://{}:{}/update-mock-internal/{}",
+            ip, peer_port, mock_id
+        );
         let client_clone = client.clone();
+        let token = state.config.sync.token.clone();
         let updated_mock_clone = updated_mock.clone();
 
         spawn(async move {
             let _ = client_clone
                 .put(&url)
+                .header("X-Internal-Token", token)
                 .json(&updated_mock_clone)
                 .send()
                 .await;
         });
     }
 
-    HttpResponse::Ok().json(updated_mock)
+    Ok(updated_mock)
 }
 
 /// Endpoint to retrieve a single mock by ID
@@ -99,32 +126,542 @@ pub async fn get_mock(path: web::Path<Uuid>, state: web::Data<AppState>) -> impl
     }
 }
 
+/// Endpoint to reset the call counter of a sequenced mock back to its first response
+#[post("/reset-sequence/{id}")]
+pub async fn reset_sequence(path: web::Path<Uuid>, state: web::Data<AppState>) -> impl Responder {
+    let id = path.into_inner();
+
+    if !state.mocks.contains_key(&id) {
+        return HttpResponse::NotFound().json("Mock not found");
+    }
+    state.sequence_counters.remove(&id);
+
+    HttpResponse::Ok().json("Sequence reset successfully")
+}
+
+/// Endpoint to reset the call counters of all sequenced mocks
+#[post("/reset-all-sequences")]
+pub async fn reset_all_sequences(state: web::Data<AppState>) -> impl Responder {
+    state.sequence_counters.clear();
+
+    HttpResponse::Ok().json("All sequences reset successfully")
+}
+
+/// Endpoint to reset the rate limit of a mock, for every client
+#[post("/reset-rate-limit/{id}")]
+pub async fn reset_rate_limit(path: web::Path<Uuid>, state: web::Data<AppState>) -> impl Responder {
+    let id = path.into_inner();
+
+    if !state.mocks.contains_key(&id) {
+        return HttpResponse::NotFound().json("Mock not found");
+    }
+    state.reset_rate_limits(id);
+
+    HttpResponse::Ok().json("Rate limit reset successfully")
+}
+
+/// Endpoint to reset the rate limits of all mocks
+#[post("/reset-all-rate-limits")]
+pub async fn reset_all_rate_limits(state: web::Data<AppState>) -> impl Responder {
+    state.rate_limits.clear();
+
+    HttpResponse::Ok().json("All rate limits reset successfully")
+}
+
+/// Endpoint to list the current state of every known scenario
+#[get("/list-scenarios")]
+pub async fn list_scenarios(state: web::Data<AppState>) -> impl Responder {
+    // Scenarios referenced by mocks but never moved are still in their initial state
+    let mut scenarios: BTreeMap<String, String> = state
+        .mocks
+        .iter()
+        .filter_map(|entry| entry.value().scenario.clone())
+        .map(|name| (name, STARTED_STATE.to_string()))
+        .collect();
+    for entry in state.scenarios.iter() {
+        scenarios.insert(entry.key().clone(), entry.value().clone());
+    }
+
+    HttpResponse::Ok().json(scenarios)
+}
+
+/// Endpoint to force a scenario into a given state
+#[put("/set-scenario-state/{name}")]
+pub async fn set_scenario_state(
+    path: web::Path<String>,
+    data: web::Json<ScenarioStateUpdate>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let name = path.into_inner();
+    let update = data.into_inner();
+
+    state.set_scenario_state(&name, &update.state);
+
+    HttpResponse::Ok().json(update)
+}
+
+/// Endpoint to reset a scenario back to its initial state
+#[post("/reset-scenario/{name}")]
+pub async fn reset_scenario(path: web::Path<String>, state: web::Data<AppState>) -> impl Responder {
+    let name = path.into_inner();
+    state.scenarios.remove(&name);
+
+    HttpResponse::Ok().json("Scenario reset successfully")
+}
+
+/// Endpoint to reset every scenario back to its initial state
+#[post("/reset-all-scenarios")]
+pub async fn reset_all_scenarios(state: web::Data<AppState>) -> impl Responder {
+    state.scenarios.clear();
+
+    HttpResponse::Ok().json("All scenarios reset successfully")
+}
+
+/// Endpoint to view the proxy fallback configuration
+#[get("/proxy-config")]
+pub async fn get_proxy_config(state: web::Data<AppState>) -> impl Responder {
+    let config = state.proxy.read().unwrap().clone();
+    HttpResponse::Ok().json(config)
+}
+
+/// Endpoint to replace the proxy fallback configuration
+#[put("/proxy-config")]
+pub async fn set_proxy_config(
+    data: web::Json<ProxyConfig>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let config = data.into_inner();
+    if let Err(e) = config.validate() {
+        return HttpResponse::BadRequest().json(e);
+    }
+
+    *state.proxy.write().unwrap() = config.clone();
+    info!("Proxy configuration updated");
+
+    HttpResponse::Ok().json(config)
+}
+
+/// Endpoint to start recording proxied traffic as mocks
+#[post("/start-recording")]
+pub async fn start_recording(
+    data: Option<web::Json<RecordingConfig>>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let config = data.map(|d| d.into_inner()).unwrap_or_default();
+    if let Err(e) = config.validate() {
+        return HttpResponse::BadRequest().json(e);
+    }
+    {
+        let proxy = state.proxy.read().unwrap();
+        if proxy.upstream.is_none() && proxy.prefixes.is_empty() {
+            return HttpResponse::BadRequest().json("Recording requires a proxy upstream");
+        }
+    }
+
+    *state.recording.write().unwrap() = Some(config.clone());
+    info!("Recording started");
+
+    HttpResponse::Ok().json(RecordingStatus {
+        recording: true,
+        config: Some(config),
+    })
+}
+
+/// Endpoint to stop recording proxied traffic
+#[post("/stop-recording")]
+pub async fn stop_recording(state: web::Data<AppState>) -> impl Responder {
+    *state.recording.write().unwrap() = None;
+    info!("Recording stopped");
+
+    HttpResponse::Ok().json(RecordingStatus {
+        recording: false,
+        config: None,
+    })
+}
+
+/// Endpoint to check whether proxied traffic is being recorded
+#[get("/recording-status")]
+pub async fn recording_status(state: web::Data<AppState>) -> impl Responder {
+    let config = state.recording.read().unwrap().clone();
+
+    HttpResponse::Ok().json(RecordingStatus {
+        recording: config.is_some(),
+        config,
+    })
+}
+
+/// Endpoint to view the global delay multiplier
+#[get("/delay-multiplier")]
+pub async fn get_delay_multiplier(state: web::Data<AppState>) -> impl Responder {
+    let multiplier = *state.delay_multiplier.read().unwrap();
+    HttpResponse::Ok().json(DelayMultiplier { multiplier })
+}
+
+/// Endpoint to scale the delay of every mock at once
+#[put("/delay-multiplier")]
+pub async fn set_delay_multiplier(
+    data: web::Json<DelayMultiplier>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let update = data.into_inner();
+    if !(0.0..=MAX_DELAY_MULTIPLIER).contains(&update.multiplier) {
+        return HttpResponse::BadRequest().json(format!(
+            "Multiplier must be a number between 0 and {}",
+            MAX_DELAY_MULTIPLIER
+        ));
+    }
+
+    *state.delay_multiplier.write().unwrap() = update.multiplier;
+    info!("Delay multiplier set to {}", update.multiplier);
+
+    HttpResponse::Ok().json(update)
+}
+
+/// Endpoint to list the connected WebSocket clients
+#[get("/list-ws-sessions")]
+pub async fn list_ws_sessions(state: web::Data<AppState>) -> impl Responder {
+    let sessions: Vec<WsSessionInfo> = state
+        .ws_sessions
+        .iter()
+        .map(|entry| entry.info.clone())
+        .collect();
+
+    HttpResponse::Ok().json(sessions)
+}
+
+/// Endpoint to push a message to a connected WebSocket client
+#[post("/send-ws-message/{id}")]
+pub async fn send_ws_message(
+    path: web::Path<Uuid>,
+    data: web::Json<WsPush>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let mut session = match state.ws_sessions.get(&id) {
+        Some(entry) => entry.session.clone(),
+        None => return HttpResponse::NotFound().json("WebSocket session not found"),
+    };
+    if session.text(data.into_inner().message).await.is_err() {
+        state.ws_sessions.remove(&id);
+        return HttpResponse::NotFound().json("WebSocket session not found");
+    }
+
+    HttpResponse::Ok().json("Message sent successfully")
+}
+
+/// Endpoint to close a connected WebSocket client
+#[post("/close-ws-session/{id}")]
+pub async fn close_ws_session(path: web::Path<Uuid>, state: web::Data<AppState>) -> impl Responder {
+    let id = path.into_inner();
+
+    match state.ws_sessions.remove(&id) {
+        Some((_, entry)) => {
+            let _ = entry.session.close(None).await;
+            HttpResponse::Ok().json("WebSocket session closed successfully")
+        }
+        None => HttpResponse::NotFound().json("WebSocket session not found"),
+    }
+}
+
+/// Endpoint to upload a binary FileDescriptorSet for gRPC mocks, then propagate it to the other pods
+#[post("/upload-descriptors")]
+pub async fn upload_descriptors(body: web::Bytes, state: web::Data<AppState>) -> impl Responder {
+    let methods = match add_descriptors(&state, &body) {
+        Ok(methods) => methods,
+        Err(response) => return response,
+    };
+
+    // Synchronize with other pods
+    let other_pod_ips = match get_other_pod_ips(&state.config.sync).await {
+        Ok(ips) => ips,
+        Err(e) => {
+            eprintln!("Failed to get other pod IPs: {}", e);
+            Vec::new()
+        }
+    };
+
+    let client = Client::new();
+    let peer_port = state.config.peer_port();
+
+    for ip in other_pod_ips {
+        let url = format!("http://{}:{}/upload-descriptors-internal", ip, peer_port);
+        let client_clone = client.clone();
+        let token = state.config.sync.token.clone();
+        let body_clone = body.clone();
+
+        spawn(async move {
+            let _ = client_clone
+                .post(&url)
+                .header("X-Internal-Token", token)
+                .body(body_clone)
+                .send()
+                .await;
+        });
+    }
+
+    HttpResponse::Ok().json(methods)
+}
+
+/// Internal endpoint to upload descriptors (used for synchronization)
+#[post("/upload-descriptors-internal")]
+pub async fn upload_descriptors_internal(
+    req: HttpRequest,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    // Validate a custom header for authentication
+    if !is_internal_request(&req, &state) {
+        return HttpResponse::Unauthorized().json("Unauthorized");
+    }
+
+    match add_descriptors(&state, &body) {
+        Ok(_) => HttpResponse::Ok().json("Descriptors uploaded internally"),
+        Err(response) => response,
+    }
+}
+
+/// Endpoint to list the gRPC methods of the uploaded descriptors
+#[get("/list-grpc-methods")]
+pub async fn list_grpc_methods(state: web::Data<AppState>) -> impl Responder {
+    let pool = state.grpc_descriptors.read().unwrap();
+    HttpResponse::Ok().json(grpc::list_methods(&pool))
+}
+
+/// Add a FileDescriptorSet to the descriptor pool; files already uploaded are kept
+fn add_descriptors(state: &AppState, body: &[u8]) -> Result<Vec<GrpcMethodInfo>, HttpResponse> {
+    let mut pool = state.grpc_descriptors.write().unwrap();
+    let mut updated = pool.clone();
+    if let Err(e) = updated.decode_file_descriptor_set(body) {
+        return Err(HttpResponse::BadRequest().json(format!("Invalid FileDescriptorSet: {}", e)));
+    }
+    *pool = updated;
+
+    let methods = grpc::list_methods(&pool);
+    info!("Descriptors uploaded, {} gRPC methods known", methods.len());
+    Ok(methods)
+}
+
+/// Endpoint to list the journaled requests, optionally filtered
+#[get("/list-requests")]
+pub async fn list_requests(
+    filter: web::Query<JournalFilter>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let filter = filter.into_inner();
+    if let Err(e) = filter.validate() {
+        return HttpResponse::BadRequest().json(e);
+    }
+
+    HttpResponse::Ok().json(state.journal.entries(&filter))
+}
+
+/// Endpoint to clear the request journal
+#[post("/clear-requests")]
+pub async fn clear_requests(state: web::Data<AppState>) -> impl Responder {
+    state.journal.clear();
+
+    HttpResponse::Ok().json("Request journal cleared successfully")
+}
+
+/// Endpoint to check how many journaled requests match a pattern
+#[post("/verify-requests")]
+pub async fn verify_requests(
+    data: web::Json<VerifyRequest>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let journal = state.journal.entries(&JournalFilter::default());
+    let max_mismatched = state.near_miss.read().unwrap().max_candidates;
+    match data.check(journal, max_mismatched) {
+        Ok(result) => HttpResponse::Ok().json(result),
+        Err(e) => HttpResponse::BadRequest().json(e),
+    }
+}
+
+/// Endpoint to list the journaled requests no mock served, with their closest mocks
+#[get("/list-near-misses")]
+pub async fn list_near_misses(
+    filter: web::Query<JournalFilter>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let filter = filter.into_inner();
+    if let Err(e) = filter.validate() {
+        return HttpResponse::BadRequest().json(e);
+    }
+
+    let limit = state.near_miss.read().unwrap().max_candidates;
+    HttpResponse::Ok().json(diagnostics::unmatched_requests(&state, &filter, limit))
+}
+
+/// Endpoint to view the near-miss diagnostics settings
+#[get("/near-miss-config")]
+pub async fn get_near_miss_config(state: web::Data<AppState>) -> impl Responder {
+    let config = state.near_miss.read().unwrap().clone();
+    HttpResponse::Ok().json(config)
+}
+
+/// Endpoint to turn near-miss diagnostics on or off
+#[put("/near-miss-config")]
+pub async fn set_near_miss_config(
+    data: web::Json<NearMissConfig>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let config = data.into_inner();
+    *state.near_miss.write().unwrap() = config.clone();
+    info!(
+        "Near-miss diagnostics {}",
+        if config.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+
+    HttpResponse::Ok().json(config)
+}
+
+/// Endpoint to view the global CORS policy
+#[get("/cors-config")]
+pub async fn get_cors_config(state: web::Data<AppState>) -> impl Responder {
+    let config = state.cors.read().unwrap().clone();
+    HttpResponse::Ok().json(config)
+}
+
+/// Endpoint to replace the global CORS policy
+#[put("/cors-config")]
+pub async fn set_cors_config(
+    data: web::Json<CorsConfig>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let config = data.into_inner();
+    if let Err(e) = config.validate() {
+        return HttpResponse::BadRequest().json(e);
+    }
+
+    *state.cors.write().unwrap() = config.clone();
+    info!("CORS policy updated");
+
+    HttpResponse::Ok().json(config)
+}
+
+/// Endpoint to download the CA certificate generated for the HTTPS listener
+#[get("/tls-ca")]
+pub async fn get_tls_ca(state: web::Data<AppState>) -> impl Responder {
+    match &state.tls_ca {
+        Some(ca) => HttpResponse::Ok()
+            .content_type("application/x-pem-file")
+            .body(ca.pem()),
+        None => HttpResponse::NotFound()
+            .json("No CA was generated: HTTPS is off or uses a supplied certificate"),
+    }
+}
+
+/// Endpoint to issue a client certificate signed by the generated CA, for mTLS clients
+#[post("/tls-client-cert")]
+pub async fn issue_client_cert(
+    data: web::Json<ClientCertRequest>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let ca = match &state.tls_ca {
+        Some(ca) => ca,
+        None => {
+            return HttpResponse::NotFound()
+                .json("No CA was generated: HTTPS is off or uses a supplied certificate")
+        }
+    };
+    if data.common_name.trim().is_empty() {
+        return HttpResponse::BadRequest().json("common_name is required");
+    }
+
+    match ca.issue_client(&data.common_name) {
+        Ok(issued) => {
+            info!("Issued a client certificate for {}", data.common_name);
+            HttpResponse::Ok().json(issued)
+        }
+        Err(e) => {
+            error!("Failed to issue a client certificate: {}", e);
+            HttpResponse::InternalServerError().json(e)
+        }
+    }
+}
+
+/// Endpoint to list the callbacks fired by mocks, optionally filtered
+#[get("/list-callbacks")]
+pub async fn list_callbacks(
+    filter: web::Query<CallbackFilter>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    HttpResponse::Ok().json(state.callback_log.records(&filter))
+}
+
+/// Endpoint to clear the callback log
+#[post("/clear-callbacks")]
+pub async fn clear_callbacks(state: web::Data<AppState>) -> impl Responder {
+    state.callback_log.clear();
+
+    HttpResponse::Ok().json("Callback log cleared successfully")
+}
+
+/// Endpoint to view the request journal limits
+#[get("/journal-config")]
+pub async fn get_journal_config(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(state.journal.config())
+}
+
+/// Endpoint to change the request journal limits
+#[put("/journal-config")]
+pub async fn set_journal_config(
+    data: web::Json<JournalConfig>,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let config = data.into_inner();
+    state.journal.set_config(config.clone());
+    info!(
+        "Request journal keeps {} requests, bodies up to {} bytes",
+        config.max_entries, config.max_body_bytes
+    );
+
+    HttpResponse::Ok().json(config)
+}
+
+/// Endpoint to view the effective server configuration, with the runtime settings
+/// changed through the admin endpoints and the sync token hidden
+#[get("/server-config")]
+pub async fn get_server_config(state: web::Data<AppState>) -> impl Responder {
+    let mut config = state.config.redacted();
+    config.proxy = state.proxy.read().unwrap().clone();
+    config.journal = state.journal.config();
+    config.near_miss = state.near_miss.read().unwrap().clone();
+    config.cors = state.cors.read().unwrap().clone();
+    HttpResponse::Ok().json(config)
+}
+
 /// Endpoint to save a new mock
 #[post("/save-mock")]
 pub async fn save_mock(data: web::Json<MockAPI>, state: web::Data<AppState>) -> impl Responder {
-    let mut mock = data.into_inner();
+    match create_mock(&state, data.into_inner()).await {
+        Ok(mock) => HttpResponse::Ok().json(mock),
+        Err(response) => response,
+    }
+}
+
+/// Validate, store and index a new mock, then propagate it to the other pods
+pub async fn create_mock(state: &AppState, mut mock: MockAPI) -> Result<MockAPI, HttpResponse> {
     let mock_id = Uuid::new_v4();
     mock.id = Some(mock_id);
 
-    // Register the template
-    let template_name = mock_id.to_string();
-    let mut handlebars = state.handlebars.lock().unwrap();
-    match handlebars.register_template_string(&template_name, &mock.response) {
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("Error compiling template: {}", e);
-            return HttpResponse::InternalServerError().json("Error compiling template");
-        }
+    if let Err(e) = validate_response(&mock) {
+        return Err(HttpResponse::BadRequest().json(e));
     }
 
-    // Insert into mocks
-    state.mocks.insert(mock_id, mock.clone());
-
-    // Map api_name to ID
-    state.api_name_to_id.insert(mock.api_name.clone(), mock_id);
+    // Store and index the mock, rejecting duplicates of an existing
+    // (method, api_name, matchers) variant
+    if let Err(e) = state.add_mock(mock_id, &mock) {
+        return Err(swap_error_response(e));
+    }
 
     // Synchronize with other pods
-    let other_pod_ips = match get_other_pod_ips().await {
+    let other_pod_ips = match get_other_pod_ips(&state.config.sync).await {
         Ok(ips) => ips,
         Err(e) => {
             eprintln!("Failed to get other pod IPs: {}", e);
@@ -133,23 +670,25 @@ pub async fn save_mock(data: web::Json<MockAPI>, state: web::Data<AppState>) ->
     };
 
     let client = Client::new();
+    let peer_port = state.config.peer_port();
 
     for ip in other_pod_ips {
-        let url = format!("http://{}:8080/save-mock-internal", ip);
+        let url = format!("http://{}:{}/save-mock-internal", ip, peer_port);
         let client_clone = client.clone();
+        let token = state.config.sync.token.clone();
         let mock_clone = mock.clone();
 
         spawn(async move {
             let _ = client_clone
                 .post(&url)
-                .header("X-Internal-Token", "S8d6xG1dA3fN7K9mA2jH4R6kB8vL0T5w")
+                .header("X-Internal-Token", token)
                 .json(&mock_clone)
                 .send()
                 .await;
         });
     }
 
-    HttpResponse::Ok().json(mock)
+    Ok(mock)
 }
 
 /// Endpoint to list all mocks
@@ -171,19 +710,19 @@ pub async fn delete_mock(path: web::Path<Uuid>, state: web::Data<AppState>) -> i
 
     // Perform local mutation
     if let Some((_, mock)) = state.mocks.remove(&id) {
-        // Remove from api_name_to_id mapping
-        state.api_name_to_id.remove(&mock.api_name);
+        // Remove from the routing index
+        state.unindex_mock(id, &mock);
 
-        // Unregister the template
-        let template_name = id.to_string();
-        let mut handlebars = state.handlebars.lock().unwrap();
-        handlebars.unregister_template(&template_name);
+        // Unregister the templates and drop the sequence counter and limiter state
+        state.unregister_templates(id, &mock);
+        state.sequence_counters.remove(&id);
+        state.reset_rate_limits(id);
     } else {
         return HttpResponse::NotFound().json("Mock not found");
     }
 
     // Synchronize with other pods
-    let other_pod_ips = match get_other_pod_ips().await {
+    let other_pod_ips = match get_other_pod_ips(&state.config.sync).await {
         Ok(ips) => ips,
         Err(e) => {
             eprintln!("Failed to get other pod IPs: {}", e);
@@ -192,15 +731,17 @@ pub async fn delete_mock(path: web::Path<Uuid>, state: web::Data<AppState>) -> i
     };
 
     let client = Client::new();
+    let peer_port = state.config.peer_port();
 
     for ip in other_pod_ips {
-        let url = format!("http://{}:8080/delete-mock-internal/{}", ip, id);
+        let url = format!("http://{}:{}/delete-mock-internal/{}", ip, peer_port, id);
         let client_clone = client.clone();
+        let token = state.config.sync.token.clone();
 
         spawn(async move {
             let _ = client_clone
                 .delete(&url)
-                .header("X-Internal-Token", "S8d6xG1dA3fN7K9mA2jH4R6kB8vL0T5w")
+                .header("X-Internal-Token", token)
                 .send()
                 .await;
         });
@@ -216,12 +757,7 @@ pub async fn delete_all_mocks_internal(
     state: web::Data<AppState>,
 ) -> impl Responder {
     // Validate a custom header for authentication
-    if req
-        .headers()
-        .get("X-Internal-Token")
-        .and_then(|h| h.to_str().ok())
-        != Some("S8d6xG1dA3fN7K9mA2jH4R6kB8vL0T5w")
-    {
+    if !is_internal_request(&req, &state) {
         return HttpResponse::Unauthorized().json("Unauthorized");
     }
 
@@ -229,7 +765,9 @@ pub async fn delete_all_mocks_internal(
 
     // Perform local mutation
     state.mocks.clear();
-    state.api_name_to_id.clear();
+    state.clear_index();
+    state.sequence_counters.clear();
+    state.rate_limits.clear();
 
     // Clear all registered templates
     let mut handlebars = state.handlebars.lock().unwrap();
@@ -247,16 +785,17 @@ pub async fn delete_all_mocks(state: web::Data<AppState>) -> impl Responder {
 
     // Perform local mutation
     state.mocks.clear();
-    state.api_name_to_id.clear();
+    state.clear_index();
+    state.sequence_counters.clear();
+    state.rate_limits.clear();
 
     // Clear all registered templates
-    let mut handlebars = state.handlebars.lock().unwrap();
-    handlebars.clear_templates();
+    state.handlebars.lock().unwrap().clear_templates();
 
     info!("Local mocks, API mappings, and templates cleared");
 
     // Synchronize with other pods
-    let other_pod_ips = match get_other_pod_ips().await {
+    let other_pod_ips = match get_other_pod_ips(&state.config.sync).await {
         Ok(ips) => ips,
         Err(e) => {
             eprintln!("Failed to get other pod IPs: {}", e);
@@ -265,15 +804,17 @@ pub async fn delete_all_mocks(state: web::Data<AppState>) -> impl Responder {
     };
 
     let client = Client::new();
+    let peer_port = state.config.peer_port();
 
     for ip in other_pod_ips {
-        let url = format!("http://{}:8080/delete-all-mocks-internal", ip);
+        let url = format!("http://{}:{}/delete-all-mocks-internal", ip, peer_port);
         let client_clone = client.clone();
+        let token = state.config.sync.token.clone();
 
         spawn(async move {
             match client_clone
                 .delete(&url)
-                .header("X-Internal-Token", "S8d6xG1dA3fN7K9mA2jH4R6kB8vL0T5w")
+                .header("X-Internal-Token", token)
                 .send()
                 .await
             {
@@ -324,12 +865,7 @@ pub async fn save_mock_internal(
     state: web::Data<AppState>,
 ) -> impl Responder {
     // Validate a custom header for authentication
-    if req
-        .headers()
-        .get("X-Internal-Token")
-        .and_then(|h| h.to_str().ok())
-        != Some("S8d6xG1dA3fN7K9mA2jH4R6kB8vL0T5w")
-    {
+    if !is_internal_request(&req, &state) {
         return HttpResponse::Unauthorized().json("Unauthorized");
     }
 
@@ -339,22 +875,12 @@ pub async fn save_mock_internal(
     let mock_id = mock.id.unwrap_or_else(Uuid::new_v4);
     mock.id = Some(mock_id);
 
-    // Register the template
-    let template_name = mock_id.to_string();
-    let mut handlebars = state.handlebars.lock().unwrap();
-    match handlebars.register_template_string(&template_name, &mock.response) {
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("Error compiling template: {}", e);
-            return HttpResponse::InternalServerError().json("Error compiling template");
-        }
+    if let Err(e) = validate_response(&mock) {
+        return HttpResponse::BadRequest().json(e);
+    }
+    if let Err(e) = state.add_mock(mock_id, &mock) {
+        return swap_error_response(e);
     }
-
-    // Insert into mocks
-    state.mocks.insert(mock_id, mock.clone());
-
-    // Map api_name to ID
-    state.api_name_to_id.insert(mock.api_name.clone(), mock_id);
 
     HttpResponse::Ok().json("Mock saved internally")
 }
@@ -368,37 +894,30 @@ pub async fn update_mock_internal(
     state: web::Data<AppState>,
 ) -> impl Responder {
     // Validate a custom header for authentication
-    if req
-        .headers()
-        .get("X-Internal-Token")
-        .and_then(|h| h.to_str().ok())
-        != Some("S8d6xG1dA3fN7K9mA2jH4R6kB8vL0T5w")
-    {
+    if !is_internal_request(&req, &state) {
         return HttpResponse::Unauthorized().json("Unauthorized");
     }
 
     let mock_id = path.into_inner();
     let updated_mock = data.into_inner();
 
+    if let Err(e) = validate_response(&updated_mock) {
+        return HttpResponse::BadRequest().json(e);
+    }
+    if let Err(e) = state.check_route(mock_id, &updated_mock) {
+        return route_error_response(e);
+    }
+
     // Perform local mutation
     if let Some(mut mock_entry) = state.mocks.get_mut(&mock_id) {
         if updated_mock.timestamp > mock_entry.timestamp {
             // Update the mock only if the incoming timestamp is newer
-            *mock_entry = updated_mock.clone();
-            state
-                .api_name_to_id
-                .insert(updated_mock.api_name.clone(), mock_id);
-
-            // Register the template
-            let template_name = mock_id.to_string();
-            let mut handlebars = state.handlebars.lock().unwrap();
-            match handlebars.register_template_string(&template_name, &updated_mock.response) {
-                Ok(_) => {}
-                Err(e) => {
-                    eprintln!("Error compiling template: {}", e);
-                    return HttpResponse::InternalServerError().json("Error compiling template");
-                }
+            if let Err(e) = state.swap_mock(mock_id, &mock_entry, &updated_mock) {
+                return swap_error_response(e);
             }
+            state.sequence_counters.remove(&mock_id);
+            state.reset_rate_limits(mock_id);
+            *mock_entry = updated_mock.clone();
 
             HttpResponse::Ok().json("Mock updated internally")
         } else {
@@ -406,20 +925,8 @@ pub async fn update_mock_internal(
         }
     } else {
         // Insert new mock
-        state.mocks.insert(mock_id, updated_mock.clone());
-        state
-            .api_name_to_id
-            .insert(updated_mock.api_name.clone(), mock_id);
-
-        // Register the template
-        let template_name = mock_id.to_string();
-        let mut handlebars = state.handlebars.lock().unwrap();
-        match handlebars.register_template_string(&template_name, &updated_mock.response) {
-            Ok(_) => {}
-            Err(e) => {
-                eprintln!("Error compiling template: {}", e);
-                return HttpResponse::InternalServerError().json("Error compiling template");
-            }
+        if let Err(e) = state.add_mock(mock_id, &updated_mock) {
+            return swap_error_response(e);
         }
 
         HttpResponse::Ok().json("Mock inserted internally")
@@ -434,25 +941,20 @@ pub async fn delete_mock_internal(
     state: web::Data<AppState>,
 ) -> impl Responder {
     // Validate a custom header for authentication
-    if req
-        .headers()
-        .get("X-Internal-Token")
-        .and_then(|h| h.to_str().ok())
-        != Some("S8d6xG1dA3fN7K9mA2jH4R6kB8vL0T5w")
-    {
+    if !is_internal_request(&req, &state) {
         return HttpResponse::Unauthorized().json("Unauthorized");
     }
 
     let id = path.into_inner();
 
     if let Some((_, mock)) = state.mocks.remove(&id) {
-        // Remove from api_name_to_id mapping
-        state.api_name_to_id.remove(&mock.api_name);
+        // Remove from the routing index
+        state.unindex_mock(id, &mock);
 
-        // Unregister the template
-        let template_name = id.to_string();
-        let mut handlebars = state.handlebars.lock().unwrap();
-        handlebars.unregister_template(&template_name);
+        // Unregister the templates and drop the sequence counter and limiter state
+        state.unregister_templates(id, &mock);
+        state.sequence_counters.remove(&id);
+        state.reset_rate_limits(id);
 
         HttpResponse::Ok().json("Mock deleted internally")
     } else {
@@ -460,13 +962,132 @@ pub async fn delete_mock_internal(
     }
 }
 
+/// Accept WebSocket upgrades on mock paths whose mock defines a `websocket` script
+#[get("/mock/{api_name:.*}", guard = "websocket::is_upgrade")]
+pub async fn ws_mock(
+    path: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+    req: HttpRequest,
+    stream: web::Payload,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let api_name = path.into_inner();
+    let query = query.into_inner();
+
+    // Upgrades are journaled like any other mock request
+    let mut entry = JournalEntry::new(&req, &api_name, &query, &[], state.journal.max_body_bytes());
+    let response = upgrade_mock(&state, api_name, &req, query, stream, &mut entry.mock_id);
+    entry.status = response.status().as_u16();
+    state.journal.record(entry);
+
+    response
+}
+
+/// Select the WebSocket mock for an upgrade request and start its session.
+/// `matched` is set to the id of the mock that was selected.
+fn upgrade_mock(
+    state: &web::Data<AppState>,
+    api_name: String,
+    req: &HttpRequest,
+    query: HashMap<String, String>,
+    stream: web::Payload,
+    matched: &mut Option<Uuid>,
+) -> HttpResponse {
+    // Pick the first WebSocket mock on this path whose matchers, scenario state and
+    // rate limit accept the upgrade request
+    let view = RequestView::new(req, &query, &[]);
+    let mut rate_decision = None;
+    let selected = state.select_variant(
+        state.route_candidates("GET", &api_name),
+        |mock| mock.websocket.is_some() && mock.matchers.as_ref().is_none_or(|m| m.matches(&view)),
+        |mock_id, mock| match &mock.rate_limit {
+            Some(spec) => {
+                let decision = state.check_rate_limit(mock_id, spec, spec.client_key(req));
+                let allowed = decision.allowed;
+                rate_decision = Some(decision);
+                allowed
+            }
+            None => true,
+        },
+    );
+    let (mock_id, path_params, mock, _) = match selected {
+        Some(selected) => selected,
+        None => return HttpResponse::NotFound().json("No WebSocket mock for this path"),
+    };
+    *matched = Some(mock_id);
+
+    // Upgrades beyond the mock's rate limit get the configured rejection instead
+    if let (Some(spec), Some(decision)) = (&mock.rate_limit, &rate_decision) {
+        if !decision.allowed {
+            info!("Rate limit of mock {} exceeded for {}", mock_id, api_name);
+            return ratelimit::limited_response(spec, decision);
+        }
+    }
+
+    let (response, session, messages) = match actix_ws::handle(req, stream) {
+        Ok(handshake) => handshake,
+        Err(e) => return e.error_response(),
+    };
+
+    // Messages are rendered with the upgrade request's context; request fields win over
+    // the built-in values, except `path` which holds the captured parameters
+    let mut context = serde_json::Map::new();
+    context.insert("api_name".to_string(), Value::String(api_name.clone()));
+    for (key, value) in req.headers().iter() {
+        if let Ok(val) = value.to_str() {
+            context.insert(key.to_string(), Value::String(val.to_string()));
+        }
+    }
+    for (key, value) in query {
+        context.insert(key, Value::String(value));
+    }
+    context.insert("path".to_string(), Value::Object(path_params));
+
+    let session_id = Uuid::new_v4();
+    let info = WsSessionInfo {
+        id: session_id,
+        mock_id,
+        api_name,
+        connected_at: chrono::Utc::now(),
+        messages_received: 0,
+    };
+    info!(
+        "WebSocket session {} opened on {}",
+        session_id, info.api_name
+    );
+    state.ws_sessions.insert(
+        session_id,
+        websocket::WsSession {
+            info,
+            session: session.clone(),
+        },
+    );
+
+    let spec = mock.websocket.unwrap_or_default();
+    actix_web::rt::spawn(websocket::run_session(
+        state.clone(),
+        session_id,
+        spec,
+        context,
+        session,
+        messages,
+    ));
+
+    response
+}
+
 /// Handle mock requests based on api_name with dynamic methods and placeholders
 #[route(
     "/mock/{api_name:.*}",
     method = "GET",
     method = "POST",
     method = "PUT",
-    method = "DELETE"
+    method = "DELETE",
+    method = "PATCH",
+    method = "HEAD",
+    method = "OPTIONS",
+    method = "TRACE",
+    method = "CONNECT"
 )]
 pub async fn handle_mock(
     path: web::Path<String>,
@@ -476,98 +1097,725 @@ pub async fn handle_mock(
     state: web::Data<AppState>,
 ) -> impl Responder {
     let api_name = path.into_inner();
+    let query = query.into_inner();
+
+    // Journal the request with the mock that served it and the response status
+    let mut entry = JournalEntry::new(
+        &req,
+        &api_name,
+        &query,
+        &body,
+        state.journal.max_body_bytes(),
+    );
+    let response = serve_mock(&state, &api_name, &req, &query, &body, &mut entry.mock_id).await;
+    entry.status = response.status().as_u16();
+    state.journal.record(entry);
+
+    response
+}
 
-    // Retrieve the mock ID from api_name
-    if let Some(mock_id) = state.api_name_to_id.get(&api_name) {
-        if let Some(mock) = state.mocks.get(&mock_id.value()) {
-            let mock = mock.clone();
-
-            if req.method().as_str().eq_ignore_ascii_case(&mock.method) {
-                let mut data = serde_json::Map::new();
+/// Select, render and serve the mock for a request, or proxy it when nothing matches.
+/// `matched` is set to the id of the mock that was served.
+async fn serve_mock(
+    state: &web::Data<AppState>,
+    api_name: &str,
+    req: &HttpRequest,
+    query: &HashMap<String, String>,
+    body: &web::Bytes,
+    matched: &mut Option<Uuid>,
+) -> HttpResponse {
+    // While recording, captured paths always go to the upstream so repeats are recorded too
+    let recording = state
+        .recording
+        .read()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|config| config.captures(api_name));
+    if recording && state.proxy.read().unwrap().upstream_for(api_name).is_some() {
+        let fallback = HttpResponse::NotFound().json("Mock not found");
+        return proxy_or(state, api_name, req, query, body, fallback).await;
+    }
 
-                // Add api_name to data
-                data.insert("api_name".to_string(), Value::String(api_name.clone()));
+    // Retrieve the candidate mocks for (method, api_name): literal names first, then patterns
+    let candidates = state.route_candidates(req.method().as_str(), api_name);
+    if candidates.is_empty() {
+        let fallback = if state.path_is_mocked(api_name) {
+            let builder = HttpResponse::MethodNotAllowed();
+            unmatched_response(
+                state,
+                req,
+                api_name,
+                query,
+                body,
+                builder,
+                "Method not allowed for this mock",
+            )
+        } else {
+            let builder = HttpResponse::NotFound();
+            unmatched_response(state, req, api_name, query, body, builder, "Mock not found")
+        };
+        return proxy_or(state, api_name, req, query, body, fallback).await;
+    }
 
-                // Extract headers
-                for (key, value) in req.headers().iter() {
-                    if let Ok(val) = value.to_str() {
-                        data.insert(key.to_string(), Value::String(val.to_string()));
-                    }
+    // Pick the first variant whose matchers accept the request
+    let mut request_view: Option<RequestView> = None;
+    let mut graphql_request: Option<Option<GraphQLRequest>> = None;
+    let mut rate_decision = None;
+    let selected = state.select_variant(
+        candidates,
+        |mock| {
+            // gRPC mocks are only served on the gRPC listener
+            if mock.grpc.is_some() {
+                return false;
+            }
+            if let Some(matchers) = &mock.matchers {
+                let view = request_view.get_or_insert_with(|| RequestView::new(req, query, body));
+                if !matchers.matches(view) {
+                    return false;
                 }
+            }
 
-                // Extract query parameters
-                for (key, value) in query.into_inner() {
-                    data.insert(key, Value::String(value));
+            // GraphQL mocks only match GraphQL requests for their operation
+            if let Some(graphql) = &mock.graphql {
+                let request =
+                    graphql_request.get_or_insert_with(|| GraphQLRequest::parse(body, query));
+                let operation = match request {
+                    Some(request) => request.operation_name.as_ref(),
+                    None => return false,
+                };
+                if graphql.operation.is_some() && graphql.operation.as_ref() != operation {
+                    return false;
                 }
+            }
+            true
+        },
+        |mock_id, mock| match &mock.rate_limit {
+            Some(spec) => {
+                let decision = state.check_rate_limit(mock_id, spec, spec.client_key(req));
+                let allowed = decision.allowed;
+                rate_decision = Some(decision);
+                allowed
+            }
+            None => true,
+        },
+    );
+
+    let (mock_id, path_params, mock, step) = match selected {
+        Some(selected) => selected,
+        None => {
+            let builder = HttpResponse::NotFound();
+            let message = "No mock variant matched the request";
+            let fallback = unmatched_response(state, req, api_name, query, body, builder, message);
+            return proxy_or(state, api_name, req, query, body, fallback).await;
+        }
+    };
+    *matched = Some(mock_id);
 
-                // Determine if the template uses variables from the body
-                let uses_body = mock.response.contains("{{") && mock.response.contains("}}");
-
-                // Parse request body only if necessary
-                if uses_body && !body.is_empty() {
-                    if let Some(content_type) = req.headers().get("Content-Type") {
-                        if content_type
-                            .to_str()
-                            .unwrap_or("")
-                            .contains("application/json")
-                        {
-                            // Parse the JSON body
-                            let json_body: Value = match serde_json::from_slice(&body) {
-                                Ok(json) => json,
-                                Err(e) => {
-                                    eprintln!("Failed to parse JSON body: {}", e);
-                                    return HttpResponse::BadRequest()
-                                        .json("Failed to parse JSON body");
-                                }
-                            };
-                            // Merge JSON body into data
-                            merge_json(&mut data, &json_body);
-                        }
-                    }
-                }
+    // Requests beyond the mock's rate limit get the configured rejection instead
+    if let (Some(spec), Some(decision)) = (&mock.rate_limit, &rate_decision) {
+        if !decision.allowed {
+            info!("Rate limit of mock {} exceeded for {}", mock_id, api_name);
+            return ratelimit::limited_response(spec, decision);
+        }
+    }
 
-                // Render the response template using registered template
-                let template_name = mock_id.value().to_string();
-                let handlebars = state.handlebars.lock().unwrap();
-                let rendered = match handlebars.render(&template_name, &data) {
-                    Ok(res) => res,
+    let mut data = serde_json::Map::new();
+
+    // Built-in values come first, so that request fields with the same name win. Only
+    // `path` is reserved for the captured parameters and is set last.
+
+    // Add api_name to data
+    data.insert("api_name".to_string(), Value::String(api_name.to_string()));
+
+    // Expose the negotiated HTTP version, e.g. `HTTP/2`
+    data.insert(
+        "protocol".to_string(),
+        Value::String(protocol_name(req.version()).to_string()),
+    );
+
+    // Expose the GraphQL document, variables and operation name
+    let graphql_request = graphql_request
+        .as_ref()
+        .and_then(|request| request.as_ref())
+        .filter(|_| mock.graphql.is_some());
+    if let Some(request) = graphql_request {
+        data.insert("query".to_string(), Value::String(request.query.clone()));
+        data.insert("variables".to_string(), request.variables.clone());
+        if let Some(operation) = &request.operation_name {
+            data.insert(
+                "operation_name".to_string(),
+                Value::String(operation.clone()),
+            );
+        }
+    }
+
+    // Extract headers
+    for (key, value) in req.headers().iter() {
+        if let Ok(val) = value.to_str() {
+            data.insert(key.to_string(), Value::String(val.to_string()));
+        }
+    }
+
+    // Extract query parameters. The GraphQL parameters of a GET are already exposed
+    // above, with the variables parsed.
+    for (key, value) in query {
+        if graphql_request.is_some() && graphql::is_request_param(key) {
+            continue;
+        }
+        data.insert(key.clone(), Value::String(value.clone()));
+    }
+
+    // Determine if the templates use variables from the body
+    let sse_templates = mock
+        .sse
+        .iter()
+        .flat_map(|sse| sse.events.iter().map(|e| &e.data));
+    let callback_templates = callback_template_sources(mock_id, &mock);
+    let uses_body = template_sources(&mock)
+        .iter()
+        .flat_map(|(_, body, headers)| std::iter::once(*body).chain(headers.values()))
+        .chain(sse_templates)
+        .chain(callback_templates.iter().map(|(_, source)| *source))
+        .any(|template| template.contains("{{") && template.contains("}}"));
+
+    // Parse request body only if necessary
+    if uses_body && !body.is_empty() {
+        if let Some(content_type) = req.headers().get("Content-Type") {
+            if content_type
+                .to_str()
+                .unwrap_or("")
+                .contains("application/json")
+            {
+                // Parse the JSON body
+                let json_body: Value = match serde_json::from_slice(body) {
+                    Ok(json) => json,
                     Err(e) => {
-                        eprintln!("Template rendering error: {}", e);
-                        return HttpResponse::InternalServerError()
-                            .json("Template rendering error");
+                        eprintln!("Failed to parse JSON body: {}", e);
+                        return HttpResponse::BadRequest().json("Failed to parse JSON body");
                     }
                 };
+                // Merge JSON body into data
+                merge_json(&mut data, &json_body);
+            }
+        }
+    }
+
+    // Expose captured path parameters as `path.<name>`
+    data.insert("path".to_string(), Value::Object(path_params));
 
-                // Introduce delay if specified
-                if mock.delay > 0 {
-                    tokio::time::sleep(std::time::Duration::from_millis(mock.delay)).await;
+    // Serve either the mock itself or the current step of its sequence. Step headers
+    // are merged over the mock-level headers.
+    let header_templates = |step: Option<usize>, names: Vec<&String>| {
+        names
+            .into_iter()
+            .map(|name| (name.clone(), header_template_name(mock_id, step, name)))
+            .collect::<Vec<_>>()
+    };
+    let (status, delay, fault, throttle, header_names) = match step {
+        Some(i) => {
+            let current = &mock.responses[i];
+            let mut names = header_templates(
+                None,
+                mock.headers
+                    .keys()
+                    .filter(|name| {
+                        !current
+                            .headers
+                            .keys()
+                            .any(|own| own.eq_ignore_ascii_case(name))
+                    })
+                    .collect(),
+            );
+            names.extend(header_templates(step, current.headers.keys().collect()));
+            let fault = current.fault.as_ref().or(mock.fault.as_ref());
+            let throttle = current.throttle.as_ref().or(mock.throttle.as_ref());
+            (current.status, &current.delay, fault, throttle, names)
+        }
+        None => (
+            mock.status,
+            &mock.delay,
+            mock.fault.as_ref(),
+            mock.throttle.as_ref(),
+            header_templates(None, mock.headers.keys().collect()),
+        ),
+    };
+    let fault = fault.filter(|f| f.triggers()).map(|f| f.kind);
+
+    // Render the response and header templates using registered templates
+    let template_name = body_template_name(mock_id, step);
+    let (rendered, rendered_headers, callbacks) = {
+        let handlebars = state.handlebars.lock().unwrap();
+        let rendered = match handlebars.render(&template_name, &data) {
+            Ok(res) => res,
+            Err(e) => {
+                eprintln!("Template rendering error: {}", e);
+                return HttpResponse::InternalServerError().json("Template rendering error");
+            }
+        };
+
+        let mut rendered_headers = Vec::with_capacity(header_names.len());
+        for (name, header_template) in header_names {
+            match handlebars.render(&header_template, &data) {
+                Ok(value) => rendered_headers.push((name, value)),
+                Err(e) => {
+                    eprintln!("Template rendering error in header {}: {}", name, e);
+                    return HttpResponse::InternalServerError().json("Template rendering error");
                 }
+            }
+        }
 
-                // Return the rendered response with the specified status code
-                HttpResponse::build(
-                    actix_web::http::StatusCode::from_u16(mock.status)
-                        .unwrap_or(actix_web::http::StatusCode::INTERNAL_SERVER_ERROR),
-                )
-                .append_header(("Content-Type", "application/json"))
-                .body(rendered)
-            } else {
-                HttpResponse::MethodNotAllowed().json("Method not allowed for this mock")
+        // GraphQL mocks wrap the rendered data (and errors) in a response envelope
+        let rendered = match &mock.graphql {
+            Some(graphql) => {
+                let errors = if graphql.errors.is_empty() {
+                    None
+                } else {
+                    match handlebars.render(&graphql_errors_template_name(mock_id), &data) {
+                        Ok(errors) => Some(errors),
+                        Err(e) => {
+                            eprintln!("Template rendering error in GraphQL errors: {}", e);
+                            return HttpResponse::InternalServerError()
+                                .json("Template rendering error");
+                        }
+                    }
+                };
+                match graphql::envelope(&rendered, errors.as_deref()) {
+                    Ok(envelope) => envelope,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return HttpResponse::InternalServerError().json(e);
+                    }
+                }
             }
+            None => rendered,
+        };
+
+        // Callbacks see the request context and the response being served. None are fired
+        // for a simulated network failure, or for a HEAD the mock was not written for.
+        let head_fallback = req.method() == actix_web::http::Method::HEAD
+            && !mock.method.methods().iter().any(|m| m == "HEAD");
+        let callbacks = if mock.callbacks.is_empty() || fault.is_some() || head_fallback {
+            Vec::new()
         } else {
-            HttpResponse::NotFound().json("Mock not found")
+            let mut callback_data = data.clone();
+            let headers: serde_json::Map<String, Value> = rendered_headers
+                .iter()
+                .map(|(name, value)| (name.to_ascii_lowercase(), Value::String(value.clone())))
+                .collect();
+            let mut response = serde_json::Map::new();
+            response.insert("status".to_string(), Value::from(status));
+            response.insert("body".to_string(), Value::String(rendered.clone()));
+            response.insert("headers".to_string(), Value::Object(headers));
+            if let Ok(json) = serde_json::from_str::<Value>(&rendered) {
+                response.insert("json".to_string(), json);
+            }
+            callback_data.insert("response".to_string(), Value::Object(response));
+            callbacks::render_callbacks(&handlebars, mock_id, &mock, &callback_data)
+                .into_iter()
+                .filter_map(|rendered| {
+                    rendered
+                        .map_err(|record| {
+                            error!("{}", record.error.as_deref().unwrap_or_default());
+                            state.callback_log.record(*record);
+                        })
+                        .ok()
+                })
+                .collect()
+        };
+        (rendered, rendered_headers, callbacks)
+    };
+
+    // Introduce delay if specified, scaled by the global multiplier
+    let delay = state.scale_delay(delay.sample());
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+
+    // Return the rendered response with the specified status code and headers
+    let mut response = HttpResponse::build(
+        actix_web::http::StatusCode::from_u16(status)
+            .unwrap_or(actix_web::http::StatusCode::INTERNAL_SERVER_ERROR),
+    );
+    if !rendered_headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+    {
+        let content_type = if mock.sse.is_some() {
+            "text/event-stream"
+        } else {
+            "application/json"
+        };
+        response.append_header(("Content-Type", content_type));
+    }
+    for header in rate_decision.iter().flat_map(|decision| decision.headers()) {
+        response.append_header(header);
+    }
+    // A header value with several lines is sent as one header per line
+    for (name, values) in rendered_headers {
+        for value in values.lines() {
+            match HeaderValue::from_str(value) {
+                Ok(value) => {
+                    response.append_header((name.as_str(), value));
+                }
+                Err(_) => error!(
+                    "Rendered value of header {} is not a valid header value",
+                    name
+                ),
+            }
         }
+    }
+
+    // Simulate a network failure or a slow link, or stream events, instead of a plain body
+    if let Some(kind) = fault {
+        return faults::fault_response(kind, req, response, rendered);
+    }
+    let response = if let Some(spec) = mock.sse.clone() {
+        sse::sse_response(state.clone(), mock_id, spec, data, response)
     } else {
-        HttpResponse::NotFound().json("Mock not found")
+        match throttle {
+            Some(throttle) => throttle::throttled_response(throttle, response, rendered),
+            None => response.body(rendered),
+        }
+    };
+
+    // Fire the callbacks in the background once the response has been sent
+    callbacks::fire_when_sent(state.clone(), callbacks, response)
+}
+
+/// Response for a request no mock serves. With near-miss diagnostics enabled, the closest
+/// mocks and the reasons they did not match are logged and returned with the message.
+fn unmatched_response(
+    state: &AppState,
+    req: &HttpRequest,
+    api_name: &str,
+    query: &HashMap<String, String>,
+    body: &[u8],
+    mut builder: HttpResponseBuilder,
+    message: &str,
+) -> HttpResponse {
+    let config = state.near_miss.read().unwrap().clone();
+    if !config.enabled {
+        return builder.json(message);
     }
+
+    let view = RequestView::new(req, query, body);
+    let near_misses = diagnostics::near_misses(
+        state,
+        req.method().as_str(),
+        api_name,
+        &view,
+        config.max_candidates,
+    );
+    info!("{} for {} {}", message, req.method(), api_name);
+    for near_miss in &near_misses {
+        info!(
+            "  near miss {} ({}): {}",
+            near_miss.mock_id,
+            near_miss.api_name,
+            near_miss.reasons.join("; ")
+        );
+    }
+
+    builder.json(UnmatchedReport {
+        message: message.to_string(),
+        near_misses,
+    })
 }
 
-fn merge_json(data: &mut serde_json::Map<String, Value>, value: &Value) {
-    match value {
-        Value::Object(map) => {
-            for (k, v) in map {
-                data.insert(k.clone(), v.clone());
+/// Forward an unmatched request to its configured upstream, or return the fallback response.
+/// While recording, the exchange is also stored as a mock.
+async fn proxy_or(
+    state: &AppState,
+    api_name: &str,
+    req: &HttpRequest,
+    query: &HashMap<String, String>,
+    body: &web::Bytes,
+    fallback: HttpResponse,
+) -> HttpResponse {
+    let upstream = state
+        .proxy
+        .read()
+        .unwrap()
+        .upstream_for(api_name)
+        .map(str::to_string);
+    let upstream = match upstream {
+        Some(upstream) => upstream,
+        None => return fallback,
+    };
+
+    let recording = state
+        .recording
+        .read()
+        .unwrap()
+        .clone()
+        .filter(|config| config.captures(api_name));
+    let proxied = match proxy::forward(
+        &state.http_client,
+        &upstream,
+        api_name,
+        req,
+        body.to_vec(),
+        recording.is_some(),
+    )
+    .await
+    {
+        Ok(proxied) => proxied,
+        Err(e) => {
+            error!("{}", e);
+            return HttpResponse::BadGateway().json("Upstream request failed");
+        }
+    };
+
+    if let Some(config) = recording {
+        match recorder::recorded_mock(req.method().as_str(), api_name, query, &proxied) {
+            Ok(mock) => record_mock(state, &config, mock).await,
+            Err(e) => error!("Not recording {} {}: {}", req.method(), api_name, e),
+        }
+    }
+
+    proxied.to_response()
+}
+
+/// Store a recorded mock, or apply the repeat policy when the request was already recorded
+async fn record_mock(state: &AppState, config: &RecordingConfig, mock: MockAPI) {
+    let existing_id = match state.check_route(Uuid::nil(), &mock) {
+        Ok(()) => {
+            match create_mock(state, mock).await {
+                Ok(mock) => info!("Recorded mock {} for {}", mock.id.unwrap(), mock.api_name),
+                Err(response) => error!("Error recording mock: {}", response.status()),
             }
+            return;
+        }
+        Err(RouteError::Conflict(existing_id)) => existing_id,
+        Err(e) => {
+            error!("Error recording mock: {}", e);
+            return;
         }
-        _ => {}
+    };
+
+    if config.repeat != RecordRepeat::Sequence {
+        return;
+    }
+    let existing = match state.mocks.get(&existing_id) {
+        Some(existing) => existing.clone(),
+        None => return,
+    };
+    let updated = recorder::append_step(&existing, &mock);
+    match replace_mock(state, existing_id, updated).await {
+        Ok(_) => info!("Recorded next response for mock {}", existing_id),
+        Err(response) => error!("Error recording mock: {}", response.status()),
+    }
+}
+
+/// Reject invalid response header names, delay specs, faults, throttles, SSE streams
+/// and WebSocket scripts
+fn validate_response(mock: &MockAPI) -> Result<(), String> {
+    let headers = std::iter::once(&mock.headers).chain(mock.responses.iter().map(|r| &r.headers));
+    for name in headers.flat_map(|h| h.keys()) {
+        if HeaderName::from_bytes(name.as_bytes()).is_err() {
+            return Err(format!("Invalid header name: {}", name));
+        }
+    }
+
+    let delays = std::iter::once(&mock.delay).chain(mock.responses.iter().map(|r| &r.delay));
+    for delay in delays {
+        delay.validate()?;
+    }
+
+    let faults = mock
+        .fault
+        .iter()
+        .chain(mock.responses.iter().filter_map(|r| r.fault.as_ref()));
+    for fault in faults {
+        fault.validate()?;
+    }
+
+    let throttles = mock
+        .throttle
+        .iter()
+        .chain(mock.responses.iter().filter_map(|r| r.throttle.as_ref()));
+    for throttle in throttles {
+        throttle.validate()?;
+    }
+
+    if let Some(sse) = &mock.sse {
+        sse.validate()?;
+    }
+    if let Some(websocket) = &mock.websocket {
+        websocket.validate()?;
+    }
+    if let Some(grpc) = &mock.grpc {
+        grpc.validate(&mock.api_name, &mock.method)?;
+    }
+    if let Some(rate_limit) = &mock.rate_limit {
+        rate_limit.validate()?;
+    }
+    if let Some(cors) = &mock.cors {
+        cors.validate()?;
+    }
+    for callback in &mock.callbacks {
+        callback.validate()?;
+    }
+    Ok(())
+}
+
+/// Returns true when a request carries the token shared by the pods for synchronization
+fn is_internal_request(req: &HttpRequest, state: &AppState) -> bool {
+    req.headers()
+        .get("X-Internal-Token")
+        .and_then(|h| h.to_str().ok())
+        == Some(state.config.sync.token.as_str())
+}
+
+/// Map a routing index error to the matching HTTP response
+fn swap_error_response(e: SwapError) -> HttpResponse {
+    match e {
+        SwapError::Route(e) => route_error_response(e),
+        SwapError::Template(e) => {
+            eprintln!("Error compiling template: {}", e);
+            HttpResponse::InternalServerError().json("Error compiling template")
+        }
+    }
+}
+
+fn route_error_response(e: RouteError) -> HttpResponse {
+    match e {
+        RouteError::InvalidMethod(_)
+        | RouteError::InvalidPattern(_)
+        | RouteError::InvalidMatchers(_) => HttpResponse::BadRequest().json(e.to_string()),
+        RouteError::Conflict(_) => HttpResponse::Conflict().json(e.to_string()),
+    }
+}
+
+fn merge_json(data: &mut serde_json::Map<String, Value>, value: &Value) {
+    if let Value::Object(map) = value {
+        for (k, v) in map {
+            data.insert(k.clone(), v.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{GraphQLSpec, MethodSpec, Settings};
+    use actix_web::{test, App};
+
+    async fn state_with(mocks: Vec<MockAPI>) -> web::Data<AppState> {
+        let state = web::Data::new(AppState::new(Settings::default(), Client::new(), None));
+        for mock in mocks {
+            create_mock(&state, mock).await.unwrap();
+        }
+        state
+    }
+
+    fn mock(api_name: &str, method: &str, response: &str) -> MockAPI {
+        MockAPI {
+            api_name: api_name.to_string(),
+            method: MethodSpec::One(method.to_string()),
+            response: response.to_string(),
+            status: 200,
+            ..Default::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn request_fields_named_path_keep_the_captured_parameters() {
+        let state = state_with(vec![
+            mock("files/{id}", "PUT", "{{path.id}} {{name}}"),
+            mock("users/{id}", "GET", "{{path.id}} {{api_name}}"),
+        ])
+        .await;
+        let app = test::init_service(App::new().app_data(state).service(handle_mock)).await;
+
+        let req = test::TestRequest::put()
+            .uri("/mock/files/7")
+            .set_json(serde_json::json!({ "path": "/tmp/a", "name": "a" }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "7 a");
+
+        let req = test::TestRequest::get()
+            .uri("/mock/users/42?path=x&api_name=override")
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "42 override");
+    }
+
+    #[actix_web::test]
+    async fn conflicting_mocks_are_not_stored() {
+        let state = state_with(vec![mock("orders", "GET", "first")]).await;
+
+        let response = create_mock(&state, mock("orders", "GET", "second"))
+            .await
+            .unwrap_err();
+        assert_eq!(response.status(), 409);
+        assert_eq!(state.mocks.len(), 1);
+        assert_eq!(state.route_candidates("GET", "orders").len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn exhausted_sequences_do_not_use_up_the_rate_limit() {
+        let once: MockAPI = serde_json::from_value(serde_json::json!({
+            "api_name": "orders",
+            "method": "GET",
+            "matchers": { "headers": { "X-Tenant": { "present": true } } },
+            "responses": [{ "response": "first" }],
+            "sequence_mode": "once_then_next",
+            "rate_limit": { "limit": 1, "window": 60000 },
+            "timestamp": "2024-11-09T12:00:00Z"
+        }))
+        .unwrap();
+        let state = state_with(vec![once, mock("orders", "GET", "fallback")]).await;
+        let app = test::init_service(App::new().app_data(state).service(handle_mock)).await;
+
+        for expected in ["first", "fallback", "fallback"] {
+            let req = test::TestRequest::get()
+                .uri("/mock/orders")
+                .insert_header(("X-Tenant", "acme"))
+                .to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            assert_eq!(body, expected);
+        }
+    }
+
+    #[actix_web::test]
+    async fn graphql_mocks_are_selected_by_operation_name() {
+        let graphql = |operation: &str, response: &str| MockAPI {
+            graphql: Some(GraphQLSpec {
+                operation: Some(operation.to_string()),
+                ..Default::default()
+            }),
+            ..mock("graphql", "ANY", response)
+        };
+        let state = state_with(vec![
+            graphql("GetUser", r#"{"user": {"id": "{{variables.id}}"}}"#),
+            graphql("ListUsers", r#"{"users": []}"#),
+        ])
+        .await;
+        let app = test::init_service(App::new().app_data(state).service(handle_mock)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/mock/graphql")
+            .set_json(serde_json::json!({
+                "query": "query GetUser($id: ID!) { user(id: $id) { id } }",
+                "variables": { "id": "7" }
+            }))
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, r#"{"data":{"user":{"id":"7"}}}"#);
+
+        let req = test::TestRequest::get()
+            .uri("/mock/graphql?query=%7B%20users%20%7D&operationName=ListUsers")
+            .to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, r#"{"data":{"users":[]}}"#);
+
+        let req = test::TestRequest::post()
+            .uri("/mock/graphql")
+            .set_json(serde_json::json!({ "query": "query Other { x }" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 404);
     }
 }
@@ -0,0 +1,157 @@
+// src/sse.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use actix_web::body::BodyStream;
+use actix_web::http::header::{HeaderValue, CACHE_CONTROL, CONTENT_ENCODING};
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use futures::stream;
+use log::error;
+use serde_json::{Map, Value};
+use std::io;
+use tokio::time::{sleep, Duration};
+use uuid::Uuid;
+
+use crate::models::{SseEvent, SseMode, SseSpec};
+use crate::state::{sse_template_name, AppState};
+
+// Interval of keep-alive comments on streams kept open without a configured interval
+const DEFAULT_KEEP_ALIVE_MS: u64 = 15_000;
+
+impl SseSpec {
+    /// Check that the stream can be served
+    pub fn validate(&self) -> Result<(), String> {
+        if self.events.is_empty() && self.mode != SseMode::KeepOpen {
+            return Err("sse requires at least one event".to_string());
+        }
+        if self.mode == SseMode::Repeat && self.events.iter().all(|e| e.delay == 0) {
+            return Err("repeating sse events need at least one non-zero delay".to_string());
+        }
+        if self.keep_alive == Some(0) {
+            return Err("sse keep_alive must be greater than 0".to_string());
+        }
+        for event in &self.events {
+            let fields = event.event.iter().chain(event.id.iter());
+            if fields
+                .into_iter()
+                .any(|f| f.contains('\n') || f.contains('\r'))
+            {
+                return Err("sse event names and ids must be single-line".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+// Position in the event stream
+enum Cursor {
+    Event(usize),
+    KeepAlive,
+}
+
+/// Stream the mock's events as `text/event-stream`, rendering each one when it is sent
+pub fn sse_response(
+    state: web::Data<AppState>,
+    mock_id: Uuid,
+    spec: SseSpec,
+    data: Map<String, Value>,
+    mut response: HttpResponseBuilder,
+) -> HttpResponse {
+    response.insert_header((CACHE_CONTROL, "no-cache"));
+
+    let data = Value::Object(data);
+    // A stream kept open without events only sends keep-alive comments
+    let start = if spec.events.is_empty() {
+        Cursor::KeepAlive
+    } else {
+        Cursor::Event(0)
+    };
+    let events = stream::unfold(Some(start), move |cursor| {
+        let state = state.clone();
+        let spec = spec.clone();
+        let data = data.clone();
+        async move {
+            match cursor? {
+                Cursor::Event(i) => {
+                    let event = spec.events.get(i)?;
+                    sleep(state.scale_delay(Duration::from_millis(event.delay))).await;
+
+                    let rendered = match render_event(&state, mock_id, i, event, &data) {
+                        Ok(rendered) => rendered,
+                        Err(e) => {
+                            error!("Template rendering error in sse event {}: {}", i, e);
+                            return Some((Err(io::Error::other(e)), None));
+                        }
+                    };
+
+                    let next = if i + 1 < spec.events.len() {
+                        Some(Cursor::Event(i + 1))
+                    } else {
+                        match spec.mode {
+                            SseMode::Once => None,
+                            SseMode::Repeat => Some(Cursor::Event(0)),
+                            SseMode::KeepOpen => Some(Cursor::KeepAlive),
+                        }
+                    };
+                    Some((Ok(Bytes::from(rendered)), next))
+                }
+                Cursor::KeepAlive => {
+                    // Comments keep proxies from timing the stream out and reveal disconnected clients
+                    let interval = spec.keep_alive.unwrap_or(DEFAULT_KEEP_ALIVE_MS);
+                    sleep(Duration::from_millis(interval)).await;
+                    Some((
+                        Ok(Bytes::from_static(b": keep-alive\n\n")),
+                        Some(Cursor::KeepAlive),
+                    ))
+                }
+            }
+        }
+    });
+
+    let mut response = response.body(BodyStream::new(events));
+
+    // Compression would buffer the events
+    if !response.headers().contains_key(CONTENT_ENCODING) {
+        response
+            .headers_mut()
+            .insert(CONTENT_ENCODING, HeaderValue::from_static("identity"));
+    }
+    response
+}
+
+/// Render one event in the SSE wire format
+fn render_event(
+    state: &AppState,
+    mock_id: Uuid,
+    index: usize,
+    event: &SseEvent,
+    data: &Value,
+) -> Result<String, String> {
+    let body = state
+        .handlebars
+        .lock()
+        .unwrap()
+        .render(&sse_template_name(mock_id, index), data)
+        .map_err(|e| e.to_string())?;
+
+    let mut frame = String::new();
+    if let Some(name) = &event.event {
+        frame.push_str(&format!("event: {}\n", name));
+    }
+    if let Some(id) = &event.id {
+        frame.push_str(&format!("id: {}\n", id));
+    }
+    if let Some(retry) = event.retry {
+        frame.push_str(&format!("retry: {}\n", retry));
+    }
+    for line in body.lines() {
+        frame.push_str(&format!("data: {}\n", line));
+    }
+    if body.is_empty() {
+        frame.push_str("data: \n");
+    }
+    frame.push('\n');
+    Ok(frame)
+}
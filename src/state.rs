@@ -7,27 +7,530 @@ use anyhow::Result;
 use dashmap::DashMap;
 use handlebars::Handlebars;
 use log::{error, info};
+use prost_reflect::DescriptorPool;
 use reqwest::Client;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use tokio::time::{sleep, Duration};
 use uuid::Uuid;
 
-use crate::models::MockAPI;
+use crate::callbacks::CallbackLog;
+use crate::journal::Journal;
+use crate::models::{CorsConfig, MockAPI, NearMissConfig, ProxyConfig, RecordingConfig, Settings};
+use crate::ratelimit::RateBucket;
+use crate::router::{
+    route_key, validate_methods, PathPattern, RouteEntry, RouteError, RouteKey, ANY_METHOD,
+};
+use crate::tls::CertificateAuthority;
+use crate::utils;
+use crate::websocket::WsSession;
+
+/// State every scenario is in until a mock moves it on
+pub const STARTED_STATE: &str = "Started";
+
+/// Name under which the body template of a mock (or one of its sequenced responses) is registered
+pub fn body_template_name(mock_id: Uuid, step: Option<usize>) -> String {
+    match step {
+        Some(step) => format!("{}:step:{}", mock_id, step),
+        None => mock_id.to_string(),
+    }
+}
+
+/// Name under which a response header template of a mock (or sequenced response) is registered
+pub fn header_template_name(mock_id: Uuid, step: Option<usize>, header: &str) -> String {
+    format!("{}:header:{}", body_template_name(mock_id, step), header)
+}
+
+/// Name under which the data template of an SSE event is registered
+pub fn sse_template_name(mock_id: Uuid, index: usize) -> String {
+    format!("{}:sse:{}", mock_id, index)
+}
+
+/// Name under which the GraphQL errors template of a mock is registered
+pub fn graphql_errors_template_name(mock_id: Uuid) -> String {
+    format!("{}:graphql:errors", mock_id)
+}
+
+/// Name under which a WebSocket message template is registered; reply is None for on_connect
+pub fn ws_template_name(mock_id: Uuid, reply: Option<usize>, index: usize) -> String {
+    match reply {
+        Some(reply) => format!("{}:ws:reply:{}:{}", mock_id, reply, index),
+        None => format!("{}:ws:connect:{}", mock_id, index),
+    }
+}
+
+/// Name under which a part (`url`, `body` or `header:<name>`) of a callback is registered
+pub fn callback_template_name(mock_id: Uuid, index: usize, part: &str) -> String {
+    format!("{}:callback:{}:{}", mock_id, index, part)
+}
+
+/// Every (template name, source) pair of a mock's callbacks
+pub fn callback_template_sources(mock_id: Uuid, mock: &MockAPI) -> Vec<(String, &String)> {
+    mock.callbacks
+        .iter()
+        .enumerate()
+        .flat_map(|(i, callback)| {
+            let headers = callback.headers.iter().map(move |(name, value)| {
+                let part = format!("header:{}", name);
+                (callback_template_name(mock_id, i, &part), value)
+            });
+            [
+                (callback_template_name(mock_id, i, "url"), &callback.url),
+                (callback_template_name(mock_id, i, "body"), &callback.body),
+            ]
+            .into_iter()
+            .chain(headers)
+        })
+        .collect()
+}
+
+/// Every (template name, source) pair of a mock's WebSocket messages
+fn ws_template_sources(mock_id: Uuid, mock: &MockAPI) -> Vec<(String, &String)> {
+    let ws = match &mock.websocket {
+        Some(ws) => ws,
+        None => return Vec::new(),
+    };
+    let on_connect = ws
+        .on_connect
+        .iter()
+        .enumerate()
+        .map(|(i, m)| (ws_template_name(mock_id, None, i), &m.message));
+    let replies = ws.replies.iter().enumerate().flat_map(|(r, reply)| {
+        reply
+            .messages
+            .iter()
+            .enumerate()
+            .map(move |(i, m)| (ws_template_name(mock_id, Some(r), i), &m.message))
+    });
+    on_connect.chain(replies).collect()
+}
+
+/// Every (step, body, headers) template group of a mock; step is None for the top-level response
+pub fn template_sources(
+    mock: &MockAPI,
+) -> Vec<(Option<usize>, &String, &BTreeMap<String, String>)> {
+    std::iter::once((None, &mock.response, &mock.headers))
+        .chain(
+            mock.responses
+                .iter()
+                .enumerate()
+                .map(|(i, r)| (Some(i), &r.response, &r.headers)),
+        )
+        .collect()
+}
+
+/// Compile every template of a mock (bodies, headers, events, messages, callbacks and
+/// GraphQL errors) into `handlebars`
+fn register_into(
+    handlebars: &mut Handlebars<'static>,
+    mock_id: Uuid,
+    mock: &MockAPI,
+) -> Result<(), String> {
+    for (step, body, headers) in template_sources(mock) {
+        handlebars
+            .register_template_string(&body_template_name(mock_id, step), body)
+            .map_err(|e| e.to_string())?;
+        for (name, value) in headers {
+            handlebars
+                .register_template_string(&header_template_name(mock_id, step, name), value)
+                .map_err(|e| format!("header {}: {}", name, e))?;
+        }
+    }
+    for (i, event) in mock
+        .sse
+        .iter()
+        .flat_map(|sse| sse.events.iter())
+        .enumerate()
+    {
+        handlebars
+            .register_template_string(&sse_template_name(mock_id, i), &event.data)
+            .map_err(|e| format!("sse event {}: {}", i, e))?;
+    }
+    for (name, source) in ws_template_sources(mock_id, mock) {
+        handlebars
+            .register_template_string(&name, source)
+            .map_err(|e| format!("websocket message {}: {}", name, e))?;
+    }
+    for (name, source) in callback_template_sources(mock_id, mock) {
+        handlebars
+            .register_template_string(&name, source)
+            .map_err(|e| format!("callback {}: {}", name, e))?;
+    }
+    if let Some(graphql) = mock.graphql.as_ref().filter(|g| !g.errors.is_empty()) {
+        let source = Value::Array(graphql.errors.clone()).to_string();
+        handlebars
+            .register_template_string(&graphql_errors_template_name(mock_id), source)
+            .map_err(|e| format!("graphql errors: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Why a mock could not be added or replace its previous version
+#[derive(Debug)]
+pub enum SwapError {
+    Route(RouteError),
+    Template(String),
+}
+
+impl fmt::Display for SwapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SwapError::Route(e) => e.fmt(f),
+            SwapError::Template(e) => write!(f, "Error compiling template: {}", e),
+        }
+    }
+}
+
+/// A selected mock: its id, captured path parameters, the mock and its sequence step
+pub type SelectedMock = (Uuid, Map<String, Value>, MockAPI, Option<usize>);
 
 /// Application state
 pub struct AppState {
     pub mocks: DashMap<Uuid, MockAPI>,
-    pub api_name_to_id: DashMap<String, Uuid>,
+    pub api_name_to_id: DashMap<RouteKey, Vec<RouteEntry>>, // Literal api_names, variants in rank order
+    pub path_patterns: RwLock<Vec<PathPattern>>, // Pattern api_names, most specific first
+    pub index_lock: Mutex<()>,                   // Held while a mock's route is checked and indexed
     pub handlebars: Arc<Mutex<Handlebars<'static>>>, // Changed to Arc<Mutex<Handlebars>>
-    pub synced_peers: AtomicUsize,                   // Counter for synchronized peers
+    pub sequence_counters: DashMap<Uuid, usize>, // Calls served per sequenced mock
+    pub scenarios: DashMap<String, String>,      // Current state per scenario name
+    pub proxy: RwLock<ProxyConfig>,              // Fallback upstreams for unmatched requests
+    pub http_client: Client,                     // Shared client for proxied requests
+    pub recording: RwLock<Option<RecordingConfig>>, // Set while proxied traffic is recorded
+    pub delay_multiplier: RwLock<f64>,           // Applied to every mock delay
+    pub ws_sessions: DashMap<Uuid, WsSession>,   // Connected WebSocket clients
+    pub grpc_descriptors: RwLock<DescriptorPool>, // Uploaded protobuf descriptors for gRPC mocks
+    pub journal: Journal,                        // Recent requests received on mock paths
+    pub near_miss: RwLock<NearMissConfig>,       // Diagnostics for unmatched requests
+    pub callback_log: CallbackLog,               // Outcomes of the callbacks fired by mocks
+    pub rate_limits: DashMap<(Uuid, String), RateBucket>, // Limiter state per mock and client key
+    pub cors: RwLock<CorsConfig>,                // Global CORS policy; mocks may override it
+    pub tls_ca: Option<CertificateAuthority>,    // CA generated for the HTTPS listener
+    pub config: Settings,                        // Effective configuration loaded at startup
+    pub synced_peers: AtomicUsize,               // Counter for synchronized peers
 }
 
 impl AppState {
+    /// Empty state for the effective configuration: no mocks, the configured proxy,
+    /// journal, diagnostics and CORS policy, and Handlebars with the built-in helpers
+    pub fn new(
+        settings: Settings,
+        http_client: Client,
+        tls_ca: Option<CertificateAuthority>,
+    ) -> Self {
+        let mut handlebars = Handlebars::new();
+        utils::register_helpers(&mut handlebars);
+
+        AppState {
+            mocks: DashMap::new(),
+            api_name_to_id: DashMap::new(),
+            path_patterns: RwLock::new(Vec::new()),
+            index_lock: Mutex::new(()),
+            handlebars: Arc::new(Mutex::new(handlebars)),
+            sequence_counters: DashMap::new(),
+            scenarios: DashMap::new(),
+            proxy: RwLock::new(settings.proxy.clone()),
+            http_client,
+            recording: RwLock::new(None),
+            delay_multiplier: RwLock::new(1.0),
+            ws_sessions: DashMap::new(),
+            grpc_descriptors: RwLock::new(DescriptorPool::new()),
+            journal: Journal::new(settings.journal.clone()),
+            near_miss: RwLock::new(settings.near_miss.clone()),
+            callback_log: CallbackLog::default(),
+            rate_limits: DashMap::new(),
+            cors: RwLock::new(settings.cors.clone()),
+            tls_ca,
+            config: settings,
+            synced_peers: AtomicUsize::new(0),
+        }
+    }
+
+    /// Check that a mock can be indexed under its methods and api_name without
+    /// shadowing another variant with the same matchers
+    pub fn check_route(&self, mock_id: Uuid, mock: &MockAPI) -> Result<(), RouteError> {
+        let methods = validate_methods(&mock.method).map_err(RouteError::InvalidMethod)?;
+        let entry = RouteEntry::new(mock_id, mock);
+        if let Some(matchers) = &entry.matchers {
+            matchers.validate().map_err(RouteError::InvalidMatchers)?;
+        }
+
+        for method in &methods {
+            if !PathPattern::is_pattern(&mock.api_name) {
+                let key = route_key(method, &mock.api_name);
+                if let Some(entries) = self.api_name_to_id.get(&key) {
+                    if let Some(existing) = entries
+                        .iter()
+                        .find(|e| e.mock_id != mock_id && e.same_variant(&entry))
+                    {
+                        return Err(RouteError::Conflict(existing.mock_id));
+                    }
+                }
+                continue;
+            }
+
+            let pattern = PathPattern::compile(method, &mock.api_name, entry.clone())
+                .map_err(RouteError::InvalidPattern)?;
+            let patterns = self.path_patterns.read().unwrap();
+            if let Some(existing) = patterns
+                .iter()
+                .find(|p| p.entry.mock_id != mock_id && p.same_route(&pattern))
+            {
+                return Err(RouteError::Conflict(existing.entry.mock_id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a mock to the routing index (literal map or pattern list), once per method.
+    /// The route is checked again under `index_lock`, so two conflicting mocks indexed
+    /// concurrently cannot both succeed.
+    pub fn index_mock(&self, mock_id: Uuid, mock: &MockAPI) -> Result<(), RouteError> {
+        let _guard = self.index_lock.lock().unwrap();
+        self.check_route(mock_id, mock)?;
+        let entry = RouteEntry::new(mock_id, mock);
+
+        if !PathPattern::is_pattern(&mock.api_name) {
+            for method in mock.method.methods() {
+                let mut entries = self
+                    .api_name_to_id
+                    .entry(route_key(&method, &mock.api_name))
+                    .or_default();
+                entries.retain(|e| e.mock_id != mock_id);
+                entries.push(entry.clone());
+                entries.sort_by(|a, b| a.rank_cmp(b));
+            }
+            return Ok(());
+        }
+
+        let mut compiled = Vec::new();
+        for method in mock.method.methods() {
+            compiled.push(
+                PathPattern::compile(&method, &mock.api_name, entry.clone())
+                    .map_err(RouteError::InvalidPattern)?,
+            );
+        }
+        let mut patterns = self.path_patterns.write().unwrap();
+        patterns.retain(|p| p.entry.mock_id != mock_id);
+        patterns.extend(compiled);
+        patterns.sort_by(|a, b| a.specificity_cmp(b));
+        Ok(())
+    }
+
+    /// Remove a mock from the routing index
+    pub fn unindex_mock(&self, mock_id: Uuid, mock: &MockAPI) {
+        for method in mock.method.methods() {
+            let key = route_key(&method, &mock.api_name);
+            if let Some(mut entries) = self.api_name_to_id.get_mut(&key) {
+                entries.retain(|e| e.mock_id != mock_id);
+            }
+            self.api_name_to_id
+                .remove_if(&key, |_, entries| entries.is_empty());
+        }
+        self.path_patterns
+            .write()
+            .unwrap()
+            .retain(|p| p.entry.mock_id != mock_id);
+    }
+
+    /// Clear the whole routing index
+    pub fn clear_index(&self) {
+        self.api_name_to_id.clear();
+        self.path_patterns.write().unwrap().clear();
+    }
+
+    /// Candidate mocks for a request, in evaluation order: literal variants first
+    /// (exact method before ANY), then pattern variants from the most specific
+    /// pattern down. HEAD requests fall back to the GET mocks.
+    pub fn route_candidates(
+        &self,
+        method: &str,
+        api_name: &str,
+    ) -> Vec<(Uuid, Map<String, Value>)> {
+        let method = method.to_ascii_uppercase();
+        let mut candidates: Vec<(Uuid, Map<String, Value>)> = Vec::new();
+        for key_method in [method.as_str(), ANY_METHOD] {
+            if let Some(entries) = self.api_name_to_id.get(&route_key(key_method, api_name)) {
+                candidates.extend(entries.iter().map(|e| (e.mock_id, Map::new())));
+            }
+        }
+
+        {
+            let patterns = self.path_patterns.read().unwrap();
+            candidates.extend(
+                patterns
+                    .iter()
+                    .filter(|p| p.method == method || p.method == ANY_METHOD)
+                    .filter_map(|p| p.captures(api_name).map(|params| (p.entry.mock_id, params))),
+            );
+        }
+
+        if candidates.is_empty() && method == "HEAD" {
+            return self.route_candidates("GET", api_name);
+        }
+        candidates
+    }
+
+    /// Compile and register every template of a mock (body and headers)
+    pub fn register_templates(&self, mock_id: Uuid, mock: &MockAPI) -> Result<(), String> {
+        register_into(&mut self.handlebars.lock().unwrap(), mock_id, mock)
+    }
+
+    /// Register, store and index a new mock. If it cannot be indexed, e.g. because a
+    /// conflicting mock was indexed meanwhile, it is removed again.
+    pub fn add_mock(&self, mock_id: Uuid, mock: &MockAPI) -> Result<(), SwapError> {
+        self.register_templates(mock_id, mock)
+            .map_err(SwapError::Template)?;
+        self.mocks.insert(mock_id, mock.clone());
+        if let Err(e) = self.index_mock(mock_id, mock) {
+            self.mocks.remove(&mock_id);
+            self.unregister_templates(mock_id, mock);
+            return Err(SwapError::Route(e));
+        }
+        Ok(())
+    }
+
+    /// Replace the routing entries and templates of a mock with those of its new version.
+    /// The new version is checked before anything is removed, and if it still fails
+    /// to be indexed or registered the old version is put back.
+    pub fn swap_mock(&self, mock_id: Uuid, old: &MockAPI, new: &MockAPI) -> Result<(), SwapError> {
+        self.check_route(mock_id, new).map_err(SwapError::Route)?;
+        register_into(&mut Handlebars::new(), mock_id, new).map_err(SwapError::Template)?;
+
+        self.unindex_mock(mock_id, old);
+        self.unregister_templates(mock_id, old);
+        let swapped = self
+            .index_mock(mock_id, new)
+            .map_err(SwapError::Route)
+            .and_then(|_| {
+                self.register_templates(mock_id, new)
+                    .map_err(SwapError::Template)
+            });
+        if swapped.is_err() {
+            self.unindex_mock(mock_id, new);
+            self.unregister_templates(mock_id, new);
+            if let Err(e) = self.index_mock(mock_id, old) {
+                error!("Error restoring the index of mock {}: {}", mock_id, e);
+            }
+            if let Err(e) = self.register_templates(mock_id, old) {
+                error!("Error restoring the templates of mock {}: {}", mock_id, e);
+            }
+        }
+        swapped
+    }
+
+    /// Unregister every template of a mock
+    pub fn unregister_templates(&self, mock_id: Uuid, mock: &MockAPI) {
+        let mut handlebars = self.handlebars.lock().unwrap();
+        for (step, _, headers) in template_sources(mock) {
+            handlebars.unregister_template(&body_template_name(mock_id, step));
+            for name in headers.keys() {
+                handlebars.unregister_template(&header_template_name(mock_id, step, name));
+            }
+        }
+        let events = mock.sse.as_ref().map_or(0, |sse| sse.events.len());
+        for i in 0..events {
+            handlebars.unregister_template(&sse_template_name(mock_id, i));
+        }
+        for (name, _) in ws_template_sources(mock_id, mock)
+            .into_iter()
+            .chain(callback_template_sources(mock_id, mock))
+        {
+            handlebars.unregister_template(&name);
+        }
+        handlebars.unregister_template(&graphql_errors_template_name(mock_id));
+    }
+
+    /// Pick the first candidate that `accepts` the request and whose scenario is in its
+    /// required state. Sequenced mocks advance their call counter, exhausted sequences are
+    /// skipped, and the selected mock's scenario moves to its new state.
+    /// A candidate that `admit` turns away (e.g. rate limited) is returned as is, with no
+    /// sequence or scenario side effects; the caller answers for it.
+    pub fn select_variant(
+        &self,
+        candidates: Vec<(Uuid, Map<String, Value>)>,
+        mut accepts: impl FnMut(&MockAPI) -> bool,
+        mut admit: impl FnMut(Uuid, &MockAPI) -> bool,
+    ) -> Option<SelectedMock> {
+        for (candidate_id, params) in candidates {
+            let mock = match self.mocks.get(&candidate_id) {
+                Some(mock) => mock.clone(),
+                None => continue,
+            };
+            if !accepts(&mock) {
+                continue;
+            }
+
+            // Scenario mocks only match in their required state
+            if let (Some(scenario), Some(required_state)) = (&mock.scenario, &mock.required_state) {
+                if self.scenario_state(scenario) != *required_state {
+                    continue;
+                }
+            }
+
+            // Exhausted sequences are skipped before `admit`, so only the chosen candidate is
+            // counted; the counter stays locked until it is advanced
+            let mut calls = (!mock.responses.is_empty())
+                .then(|| self.sequence_counters.entry(candidate_id).or_insert(0));
+            let step = match &calls {
+                Some(calls) => match mock.sequence_mode.step(**calls, mock.responses.len()) {
+                    Some(step) => Some(step),
+                    None => continue,
+                },
+                None => None,
+            };
+
+            if !admit(candidate_id, &mock) {
+                return Some((candidate_id, params, mock, None));
+            }
+            if let Some(calls) = &mut calls {
+                **calls += 1;
+            }
+            drop(calls);
+
+            // Serving a scenario mock may move its scenario to a new state
+            if let (Some(scenario), Some(new_state)) = (&mock.scenario, &mock.new_state) {
+                self.set_scenario_state(scenario, new_state);
+            }
+            return Some((candidate_id, params, mock, step));
+        }
+        None
+    }
+
+    /// Current state of a scenario; scenarios start in `Started`
+    pub fn scenario_state(&self, scenario: &str) -> String {
+        self.scenarios
+            .get(scenario)
+            .map(|state| state.value().clone())
+            .unwrap_or_else(|| STARTED_STATE.to_string())
+    }
+
+    /// Move a scenario to a new state
+    pub fn set_scenario_state(&self, scenario: &str, new_state: &str) {
+        info!("Scenario {} moved to state {}", scenario, new_state);
+        self.scenarios
+            .insert(scenario.to_string(), new_state.to_string());
+    }
+
+    /// Returns true when some mock serves this path under any method
+    pub fn path_is_mocked(&self, api_name: &str) -> bool {
+        self.api_name_to_id
+            .iter()
+            .any(|entry| entry.key().1 == api_name)
+            || self
+                .path_patterns
+                .read()
+                .unwrap()
+                .iter()
+                .any(|p| p.captures(api_name).is_some())
+    }
+
     /// Sync data from another pod with retries and timestamp comparison
     pub async fn sync_data_from_peer(&self, peer_ip: &str) -> Result<()> {
         let client = Client::new();
-        let url = format!("http://{}:8080/list-mocks", peer_ip);
+        let url = format!("http://{}:{}/list-mocks", peer_ip, self.config.peer_port());
 
         for attempt in 1..=3 {
             match client.get(&url).send().await {
@@ -40,35 +543,31 @@ impl AppState {
                                         // Compare timestamps
                                         if peer_mock.timestamp > local_mock.timestamp {
                                             // Update local mock with peer's mock
-                                            *local_mock = peer_mock.clone();
-                                            self.api_name_to_id
-                                                .insert(peer_mock.api_name.clone(), id);
-                                            info!("Updated mock {} from peer {}", id, peer_ip);
-
-                                            // Register the template
-                                            let template_name = id.to_string();
-                                            let mut handlebars = self.handlebars.lock().unwrap();
-                                            if let Err(e) = handlebars.register_template_string(
-                                                &template_name,
-                                                &peer_mock.response,
-                                            ) {
-                                                error!("Error compiling template: {}", e);
+                                            match self.swap_mock(id, &local_mock, &peer_mock) {
+                                                Ok(()) => {
+                                                    *local_mock = peer_mock.clone();
+                                                    info!(
+                                                        "Updated mock {} from peer {}",
+                                                        id, peer_ip
+                                                    );
+                                                }
+                                                Err(e) => error!(
+                                                    "Error updating mock {} from peer {}: {}",
+                                                    id, peer_ip, e
+                                                ),
                                             }
                                         }
                                     } else {
-                                        // Insert new mock
-                                        self.mocks.insert(id, peer_mock.clone());
-                                        self.api_name_to_id.insert(peer_mock.api_name.clone(), id);
-                                        info!("Added new mock {} from peer {}", id, peer_ip);
-
-                                        // Register the template
-                                        let template_name = id.to_string();
-                                        let mut handlebars = self.handlebars.lock().unwrap();
-                                        if let Err(e) = handlebars.register_template_string(
-                                            &template_name,
-                                            &peer_mock.response,
-                                        ) {
-                                            error!("Error compiling template: {}", e);
+                                        // Insert new mock; one conflicting with a local mock
+                                        // would be unreachable, so it is skipped
+                                        match self.add_mock(id, &peer_mock) {
+                                            Ok(()) => {
+                                                info!("Added new mock {} from peer {}", id, peer_ip)
+                                            }
+                                            Err(e) => error!(
+                                                "Skipped mock {} from peer {}: {}",
+                                                id, peer_ip, e
+                                            ),
                                         }
                                     }
                                 }
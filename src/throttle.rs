@@ -0,0 +1,76 @@
+// src/throttle.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use actix_web::body::{BodyStream, SizedStream};
+use actix_web::http::header::{HeaderValue, CONTENT_ENCODING};
+use actix_web::web::Bytes;
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use futures::stream;
+use std::io;
+use tokio::time::{sleep_until, Duration, Instant};
+
+use crate::models::ThrottleSpec;
+
+// Chunks per second when no chunk size is configured
+const DEFAULT_CHUNKS_PER_SECOND: u64 = 10;
+
+impl ThrottleSpec {
+    /// Check that the rate and chunk size are usable
+    pub fn validate(&self) -> Result<(), String> {
+        if self.bytes_per_second == 0 {
+            return Err("throttle bytes_per_second must be greater than 0".to_string());
+        }
+        if self.chunk_size == Some(0) {
+            return Err("throttle chunk_size must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    fn chunk_len(&self) -> usize {
+        self.chunk_size
+            .unwrap_or((self.bytes_per_second / DEFAULT_CHUNKS_PER_SECOND).max(1) as usize)
+    }
+}
+
+/// Stream the body in chunks: the first one after `first_byte` ms, the rest paced
+/// so the body is delivered at `bytes_per_second`
+pub fn throttled_response(
+    spec: &ThrottleSpec,
+    mut response: HttpResponseBuilder,
+    body: String,
+) -> HttpResponse {
+    let body = Bytes::from(body);
+    let len = body.len() as u64;
+    let chunk_len = spec.chunk_len();
+    let bytes_per_second = spec.bytes_per_second as f64;
+    let start = Instant::now() + Duration::from_millis(spec.first_byte);
+
+    let chunks = stream::unfold(Some(0usize), move |state| {
+        let body = body.clone();
+        async move {
+            let sent = state?;
+            // Pace against the start time so sleep overshoot does not accumulate
+            sleep_until(start + Duration::from_secs_f64(sent as f64 / bytes_per_second)).await;
+
+            let end = (sent + chunk_len).min(body.len());
+            let next = (end < body.len()).then_some(end);
+            Some((Ok::<_, io::Error>(body.slice(sent..end)), next))
+        }
+    });
+
+    let mut response = if spec.chunked {
+        response.body(BodyStream::new(chunks))
+    } else {
+        response.body(SizedStream::new(len, chunks))
+    };
+
+    // Compression would buffer the trickled chunks
+    if !response.headers().contains_key(CONTENT_ENCODING) {
+        response
+            .headers_mut()
+            .insert(CONTENT_ENCODING, HeaderValue::from_static("identity"));
+    }
+    response
+}
@@ -0,0 +1,225 @@
+// src/tls.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
+use rustls::crypto::ring::default_provider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use crate::models::{ClientAuth, IssuedCertificate, TlsConfig};
+
+impl TlsConfig {
+    /// Override the HTTPS settings with `TLS_CERT_PATH`, `TLS_KEY_PATH`, `TLS_HOSTNAMES`
+    /// (comma-separated), `TLS_CLIENT_AUTH` (none, optional or required) and
+    /// `TLS_CLIENT_CA_PATH`
+    pub fn apply_env(&mut self) -> Result<(), String> {
+        let path = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .map(|p| Some(p).filter(|p| !p.is_empty()))
+        };
+        if let Some(cert_path) = path("TLS_CERT_PATH") {
+            self.cert_path = cert_path;
+        }
+        if let Some(key_path) = path("TLS_KEY_PATH") {
+            self.key_path = key_path;
+        }
+        if let Some(client_ca_path) = path("TLS_CLIENT_CA_PATH") {
+            self.client_ca_path = client_ca_path;
+        }
+        if let Ok(names) = std::env::var("TLS_HOSTNAMES") {
+            self.hostnames = names
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Ok(mode) = std::env::var("TLS_CLIENT_AUTH") {
+            self.client_auth = serde_json::from_value(serde_json::Value::String(
+                mode.to_lowercase(),
+            ))
+            .map_err(|_| {
+                format!(
+                    "TLS_CLIENT_AUTH must be none, optional or required, got '{}'",
+                    mode
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Check that a supplied certificate comes with its key, and that client certificates
+    /// have a CA to be verified against
+    pub fn validate(&self) -> Result<(), String> {
+        if self.cert_path.is_some() != self.key_path.is_some() {
+            return Err("tls cert_path and key_path must be set together".to_string());
+        }
+        if self.cert_path.is_none() && self.hostnames.is_empty() {
+            return Err("tls hostnames must name at least one host".to_string());
+        }
+        if self.client_auth != ClientAuth::None
+            && self.cert_path.is_some()
+            && self.client_ca_path.is_none()
+        {
+            return Err(
+                "tls client_ca_path is required for client certificates with a supplied certificate"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Build the rustls configuration of the HTTPS listener. Without a supplied
+    /// certificate, a CA and a leaf certificate for `hostnames` are generated; the CA is
+    /// returned so that clients can trust it.
+    pub fn server_config(&self) -> Result<(ServerConfig, Option<CertificateAuthority>), String> {
+        let (chain, key, authority) = match (&self.cert_path, &self.key_path) {
+            (Some(cert_path), Some(key_path)) => {
+                (read_certs(cert_path)?, read_key(key_path)?, None)
+            }
+            _ => {
+                let authority = CertificateAuthority::generate()?;
+                let (leaf, leaf_key) = authority.issue("OMock", &self.hostnames, false)?;
+                let key = PrivatePkcs8KeyDer::from(leaf_key.serialize_der());
+                (
+                    vec![leaf.der().clone(), authority.cert.der().clone()],
+                    PrivateKeyDer::Pkcs8(key),
+                    Some(authority),
+                )
+            }
+        };
+
+        let provider = Arc::new(default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("TLS protocol versions: {}", e))?;
+
+        let builder = if self.client_auth == ClientAuth::None {
+            builder.with_no_client_auth()
+        } else {
+            let mut roots = RootCertStore::empty();
+            let trusted = match (&self.client_ca_path, &authority) {
+                (Some(path), _) => read_certs(path)?,
+                (None, Some(authority)) => vec![authority.cert.der().clone()],
+                (None, None) => return Err("no CA to verify client certificates".to_string()),
+            };
+            for cert in trusted {
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("invalid client CA certificate: {}", e))?;
+            }
+            let mut verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            if self.client_auth == ClientAuth::Optional {
+                verifier = verifier.allow_unauthenticated();
+            }
+            let verifier = verifier
+                .build()
+                .map_err(|e| format!("client certificate verifier: {}", e))?;
+            builder.with_client_cert_verifier(verifier)
+        };
+
+        let config = builder
+            .with_single_cert(chain, key)
+            .map_err(|e| format!("TLS certificate: {}", e))?;
+        Ok((config, authority))
+    }
+}
+
+/// CA generated at startup, signing the server certificate and client certificates
+pub struct CertificateAuthority {
+    cert: Certificate,
+    key: KeyPair,
+}
+
+impl CertificateAuthority {
+    fn generate() -> Result<Self, String> {
+        let key = KeyPair::generate().map_err(|e| format!("CA key: {}", e))?;
+        let mut params = CertificateParams::default();
+        params.distinguished_name = distinguished_name("OMock CA");
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        let cert = params
+            .self_signed(&key)
+            .map_err(|e| format!("CA certificate: {}", e))?;
+        Ok(CertificateAuthority { cert, key })
+    }
+
+    /// PEM encoded CA certificate, for clients to trust
+    pub fn pem(&self) -> String {
+        self.cert.pem()
+    }
+
+    /// Sign a server (or client) certificate for the given names
+    fn issue(
+        &self,
+        common_name: &str,
+        names: &[String],
+        client: bool,
+    ) -> Result<(Certificate, KeyPair), String> {
+        let key = KeyPair::generate().map_err(|e| format!("certificate key: {}", e))?;
+        let mut params = CertificateParams::new(names.to_vec())
+            .map_err(|e| format!("certificate names: {}", e))?;
+        params.distinguished_name = distinguished_name(common_name);
+        params.key_usages = vec![
+            KeyUsagePurpose::DigitalSignature,
+            KeyUsagePurpose::KeyEncipherment,
+        ];
+        params.extended_key_usages = vec![if client {
+            ExtendedKeyUsagePurpose::ClientAuth
+        } else {
+            ExtendedKeyUsagePurpose::ServerAuth
+        }];
+        let cert = params
+            .signed_by(&key, &self.cert, &self.key)
+            .map_err(|e| format!("certificate: {}", e))?;
+        Ok((cert, key))
+    }
+
+    /// Issue a client certificate for mTLS, signed by this CA
+    pub fn issue_client(&self, common_name: &str) -> Result<IssuedCertificate, String> {
+        let (cert, key) = self.issue(common_name, &[], true)?;
+        Ok(IssuedCertificate {
+            cert: cert.pem(),
+            key: key.serialize_pem(),
+        })
+    }
+}
+
+fn distinguished_name(common_name: &str) -> DistinguishedName {
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, common_name);
+    name
+}
+
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no PEM certificate found", path));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("{}: {}", path, e))?
+        .ok_or_else(|| format!("{}: no PEM private key found", path))
+}
@@ -1,5 +1,6 @@
 // src/utils.rs
 
+use crate::models::SyncSettings;
 use anyhow::Result;
 use chrono::Utc;
 use handlebars::{Context, Handlebars, Helper, HelperResult, Output, RenderContext};
@@ -10,7 +11,6 @@ use kube::{
 };
 use rand::{distributions::Alphanumeric, Rng};
 use regex::Regex;
-use std::env;
 use std::fs;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
@@ -22,7 +22,7 @@ fn is_running_in_kubernetes() -> bool {
 }
 
 /// Get other pod IPs if running in Kubernetes; otherwise, skip.
-pub async fn get_other_pod_ips() -> Result<Vec<String>> {
+pub async fn get_other_pod_ips(sync: &SyncSettings) -> Result<Vec<String>> {
     if !is_running_in_kubernetes() {
         println!("Not running in a Kubernetes environment; skipping pod synchronization.");
         return Ok(Vec::new());
@@ -36,12 +36,10 @@ pub async fn get_other_pod_ips() -> Result<Vec<String>> {
         }
     };
 
-    let namespace = env::var("K8S_NAMESPACE").unwrap_or_else(|_| "default".to_string());
-    let app_label = env::var("APP_LABEL").unwrap_or_else(|_| "omock".to_string());
-    let own_pod_ip = env::var("POD_IP").unwrap_or_else(|_| "".to_string());
+    let own_pod_ip = sync.pod_ip.as_deref().unwrap_or_default();
 
-    let pods: Api<Pod> = Api::namespaced(client, &namespace);
-    let lp = ListParams::default().labels(&format!("app={}", app_label));
+    let pods: Api<Pod> = Api::namespaced(client, &sync.namespace);
+    let lp = ListParams::default().labels(&format!("app={}", sync.app_label));
 
     let pod_list = pods.list(&lp).await.map_err(|e| {
         eprintln!("Failed to list pods: {}", e);
@@ -52,7 +50,7 @@ pub async fn get_other_pod_ips() -> Result<Vec<String>> {
         .items
         .into_iter()
         .filter_map(|pod| pod.status.and_then(|status| status.pod_ip))
-        .filter(|ip| ip != &own_pod_ip) // Exclude own pod IP
+        .filter(|ip| ip != own_pod_ip) // Exclude own pod IP
         .collect();
 
     if ips.is_empty() {
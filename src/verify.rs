@@ -0,0 +1,252 @@
+// src/verify.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use uuid::Uuid;
+
+use crate::matchers::RequestView;
+use crate::models::{
+    CountExpectation, JournalEntry, MethodSpec, RequestMismatch, VerifyRequest, VerifyResult,
+};
+use crate::router::{validate_methods, PathPattern, RouteEntry, ANY_METHOD};
+
+impl CountExpectation {
+    /// Check that the bounds are consistent
+    pub fn validate(&self) -> Result<(), String> {
+        if self.exactly.is_some() && (self.at_least.is_some() || self.at_most.is_some()) {
+            return Err("count.exactly cannot be combined with at_least or at_most".to_string());
+        }
+        if let (Some(min), Some(max)) = (self.at_least, self.at_most) {
+            if min > max {
+                return Err("count.at_least must not exceed count.at_most".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true when the number of matching requests is within the bounds
+    pub fn accepts(&self, count: usize) -> bool {
+        if let Some(exactly) = self.exactly {
+            return count == exactly;
+        }
+        if self.at_least.is_none() && self.at_most.is_none() {
+            return count >= 1;
+        }
+        self.at_least.is_none_or(|min| count >= min) && self.at_most.is_none_or(|max| count <= max)
+    }
+}
+
+impl VerifyRequest {
+    /// Compile the request pattern; the method, path and matchers are checked like a mock's
+    fn pattern(&self) -> Result<PathPattern, String> {
+        let method = validate_methods(&MethodSpec::One(self.method.clone()))?.remove(0);
+        if let Some(matchers) = &self.matchers {
+            matchers.validate()?;
+        }
+        self.count.validate()?;
+
+        let entry = RouteEntry {
+            mock_id: Uuid::nil(),
+            priority: 0,
+            matchers: self.matchers.clone(),
+            scenario_state: None,
+            operation: None,
+        };
+        PathPattern::compile(&method, &self.path, entry)
+            .map_err(|e| format!("path '{}': {}", self.path, e))
+    }
+
+    /// Count the journaled requests matching the pattern and compare with the expectation.
+    /// A failed check lists the matching requests and the `max_mismatched` other requests
+    /// closest to the pattern.
+    pub fn check(
+        &self,
+        journal: Vec<JournalEntry>,
+        max_mismatched: usize,
+    ) -> Result<VerifyResult, String> {
+        let pattern = self.pattern()?;
+
+        let mut matched = Vec::new();
+        let mut mismatched = Vec::new();
+        for request in journal {
+            let reasons = mismatches(&pattern, &request);
+            if reasons.is_empty() {
+                matched.push(request);
+            } else {
+                mismatched.push(RequestMismatch { request, reasons });
+            }
+        }
+
+        let count = matched.len();
+        let passed = self.count.accepts(count);
+        if passed {
+            matched.clear();
+            mismatched.clear();
+        } else {
+            // Closest first: fewest reasons, most recent among equals
+            mismatched.reverse();
+            mismatched.sort_by_key(|mismatch| mismatch.reasons.len());
+            mismatched.truncate(max_mismatched);
+        }
+        Ok(VerifyResult {
+            passed,
+            count,
+            expected: self.count.clone(),
+            matched,
+            mismatched,
+        })
+    }
+}
+
+/// Reasons a journaled request does not match the pattern; empty when it matches
+fn mismatches(pattern: &PathPattern, request: &JournalEntry) -> Vec<String> {
+    let mut reasons = Vec::new();
+    if pattern.method != ANY_METHOD && pattern.method != request.method {
+        reasons.push(format!(
+            "method: expected {}, got {}",
+            pattern.method, request.method
+        ));
+    }
+    if pattern.captures(&request.path).is_none() {
+        reasons.push(format!("path: '{}' does not match", request.path));
+    }
+    if let Some(matchers) = &pattern.entry.matchers {
+        reasons.extend(matchers.mismatches(&RequestView::from_entry(request)));
+    }
+    reasons
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(
+        exactly: Option<usize>,
+        at_least: Option<usize>,
+        at_most: Option<usize>,
+    ) -> CountExpectation {
+        CountExpectation {
+            exactly,
+            at_least,
+            at_most,
+        }
+    }
+
+    fn request(method: &str, path: &str) -> JournalEntry {
+        JournalEntry {
+            method: method.to_string(),
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn verify(method: &str, path: &str, expected: CountExpectation) -> VerifyRequest {
+        VerifyRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            matchers: None,
+            count: expected,
+        }
+    }
+
+    #[test]
+    fn exact_count() {
+        let expected = count(Some(2), None, None);
+        assert!(!expected.accepts(1));
+        assert!(expected.accepts(2));
+        assert!(!expected.accepts(3));
+    }
+
+    #[test]
+    fn at_least_and_at_most() {
+        let at_least = count(None, Some(2), None);
+        assert!(!at_least.accepts(1));
+        assert!(at_least.accepts(2));
+        assert!(at_least.accepts(100));
+
+        let at_most = count(None, None, Some(1));
+        assert!(at_most.accepts(0));
+        assert!(at_most.accepts(1));
+        assert!(!at_most.accepts(2));
+
+        let range = count(None, Some(1), Some(3));
+        assert!(!range.accepts(0));
+        assert!(range.accepts(1));
+        assert!(range.accepts(3));
+        assert!(!range.accepts(4));
+    }
+
+    #[test]
+    fn no_bound_expects_at_least_one() {
+        let expected = CountExpectation::default();
+        assert!(!expected.accepts(0));
+        assert!(expected.accepts(1));
+    }
+
+    #[test]
+    fn inconsistent_bounds_are_rejected() {
+        assert!(count(Some(1), Some(1), None).validate().is_err());
+        assert!(count(None, Some(3), Some(2)).validate().is_err());
+        assert!(count(None, Some(2), Some(2)).validate().is_ok());
+    }
+
+    #[test]
+    fn passed_check_lists_nothing() {
+        let journal = vec![request("POST", "orders/1"), request("GET", "orders/1")];
+        let result = verify("POST", "orders/{id}", count(Some(1), None, None))
+            .check(journal, 3)
+            .unwrap();
+        assert!(result.passed);
+        assert_eq!(result.count, 1);
+        assert!(result.matched.is_empty());
+        assert!(result.mismatched.is_empty());
+    }
+
+    #[test]
+    fn failed_check_lists_the_closest_mismatches() {
+        let journal = vec![
+            request("GET", "users/1"),
+            request("GET", "orders/1"),
+            request("POST", "orders/2"),
+            request("DELETE", "orders/3"),
+        ];
+        let result = verify("POST", "orders/{id}", count(Some(2), None, None))
+            .check(journal, 2)
+            .unwrap();
+        assert!(!result.passed);
+        assert_eq!(result.count, 1);
+        assert_eq!(result.matched.len(), 1);
+        assert_eq!(result.matched[0].path, "orders/2");
+
+        let mismatched: Vec<(&str, &[String])> = result
+            .mismatched
+            .iter()
+            .map(|m| (m.request.path.as_str(), m.reasons.as_slice()))
+            .collect();
+        assert_eq!(
+            mismatched,
+            [
+                (
+                    "orders/3",
+                    &["method: expected POST, got DELETE".to_string()][..]
+                ),
+                (
+                    "orders/1",
+                    &["method: expected POST, got GET".to_string()][..]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_patterns_are_reported() {
+        let result = verify("POST", "orders/{id", CountExpectation::default()).check(Vec::new(), 3);
+        assert!(result.unwrap_err().starts_with("path 'orders/{id'"));
+        assert!(
+            verify("NOT A METHOD", "orders", CountExpectation::default())
+                .check(Vec::new(), 3)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,183 @@
+// src/websocket.rs
+
+// Author: Md Hasan Basri
+// Email: pothiq@gmail.com
+
+use actix_web::guard::GuardContext;
+use actix_web::web;
+use actix_ws::{AggregatedMessage, AggregatedMessageStream, Session};
+use futures::StreamExt;
+use log::{error, info};
+use serde_json::{Map, Value};
+use tokio::time::{sleep, Duration};
+use uuid::Uuid;
+
+use crate::matchers::compiled_regex;
+use crate::models::{WebSocketSpec, WsMessage, WsReply, WsSessionInfo};
+use crate::state::{ws_template_name, AppState};
+
+/// A connected WebSocket client and the handle used to push messages to it
+pub struct WsSession {
+    pub info: WsSessionInfo,
+    pub session: Session,
+}
+
+/// Route guard selecting WebSocket upgrade requests
+pub fn is_upgrade(ctx: &GuardContext) -> bool {
+    ctx.head()
+        .headers()
+        .get("upgrade")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+impl WebSocketSpec {
+    /// Check that every reply predicate compiles
+    pub fn validate(&self) -> Result<(), String> {
+        for reply in &self.replies {
+            if let Some(pattern) = &reply.matches {
+                compiled_regex(pattern).map_err(|e| format!("reply '{}': {}", pattern, e))?;
+            }
+            for matcher in &reply.body {
+                matcher.validate()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl WsReply {
+    /// Returns true when an incoming message satisfies every predicate of this reply
+    fn accepts(&self, text: &str, json: Option<&Value>) -> bool {
+        let text_matches = match &self.matches {
+            Some(pattern) => compiled_regex(pattern).is_ok_and(|regex| regex.is_match(text)),
+            None => true,
+        };
+        text_matches && self.body.iter().all(|m| m.check(json).is_ok())
+    }
+}
+
+/// Drive a WebSocket connection: send the on_connect script, then answer incoming messages
+pub async fn run_session(
+    state: web::Data<AppState>,
+    session_id: Uuid,
+    spec: WebSocketSpec,
+    context: Map<String, Value>,
+    mut session: Session,
+    stream: actix_ws::MessageStream,
+) {
+    let mock_id = match state.ws_sessions.get(&session_id) {
+        Some(entry) => entry.info.mock_id,
+        None => return,
+    };
+    let script = spec.on_connect.iter().enumerate();
+    let connected = send_messages(&state, &mut session, &context, script, |i| {
+        ws_template_name(mock_id, None, i)
+    })
+    .await;
+    if connected {
+        answer_messages(
+            &state,
+            session_id,
+            mock_id,
+            &spec,
+            &context,
+            &mut session,
+            stream.aggregate_continuations(),
+        )
+        .await;
+    }
+
+    state.ws_sessions.remove(&session_id);
+    let _ = session.close(None).await;
+    info!("WebSocket session {} closed", session_id);
+}
+
+/// Answer each incoming message with the first matching reply until the client disconnects
+async fn answer_messages(
+    state: &AppState,
+    session_id: Uuid,
+    mock_id: Uuid,
+    spec: &WebSocketSpec,
+    context: &Map<String, Value>,
+    session: &mut Session,
+    mut stream: AggregatedMessageStream,
+) {
+    loop {
+        let text = match stream.next().await {
+            Some(Ok(AggregatedMessage::Text(text))) => text.to_string(),
+            Some(Ok(AggregatedMessage::Binary(bytes))) => match String::from_utf8(bytes.to_vec()) {
+                Ok(text) => text,
+                Err(_) => continue,
+            },
+            Some(Ok(AggregatedMessage::Ping(bytes))) => {
+                if session.pong(&bytes).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            Some(Ok(AggregatedMessage::Pong(_))) => continue,
+            Some(Ok(AggregatedMessage::Close(_))) | Some(Err(_)) | None => return,
+        };
+
+        if let Some(mut entry) = state.ws_sessions.get_mut(&session_id) {
+            entry.info.messages_received += 1;
+        }
+
+        // Replies see the request context plus the incoming message
+        let json: Option<Value> = serde_json::from_str(&text).ok();
+        let reply = spec
+            .replies
+            .iter()
+            .position(|reply| reply.accepts(&text, json.as_ref()));
+        let reply = match reply {
+            Some(reply) => reply,
+            None => continue,
+        };
+        let mut context = context.clone();
+        context.insert("message".to_string(), Value::String(text));
+        if let Some(json) = json {
+            context.insert("json".to_string(), json);
+        }
+
+        let messages = spec.replies[reply].messages.iter().enumerate();
+        let sent = send_messages(state, session, &context, messages, |i| {
+            ws_template_name(mock_id, Some(reply), i)
+        })
+        .await;
+        if !sent {
+            return;
+        }
+    }
+}
+
+/// Render and send scripted messages; returns false once the client is gone
+async fn send_messages<'a>(
+    state: &AppState,
+    session: &mut Session,
+    context: &Map<String, Value>,
+    messages: impl Iterator<Item = (usize, &'a WsMessage)>,
+    template_name: impl Fn(usize) -> String,
+) -> bool {
+    for (i, message) in messages {
+        if message.delay > 0 {
+            sleep(state.scale_delay(Duration::from_millis(message.delay))).await;
+        }
+        let rendered = state
+            .handlebars
+            .lock()
+            .unwrap()
+            .render(&template_name(i), context);
+        let rendered = match rendered {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                error!("Template rendering error in websocket message: {}", e);
+                continue;
+            }
+        };
+        if session.text(rendered).await.is_err() {
+            return false;
+        }
+    }
+    true
+}